@@ -0,0 +1,322 @@
+use anyhow::bail;
+use hmac::{Hmac, KeyInit, Mac};
+use rand::Rng;
+use rand::seq::IndexedRandom;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+const DEFAULT_ALPHABET: &str = "abcdefghijklmnopqrstuvwxyz0123456789";
+/// The local part of an email address can't be longer than this.
+const MAX_LOCAL_PART_LENGTH: usize = 64;
+/// Aliases for a service are only deterministic when a key is configured.
+const DEFAULT_SERVICE_TEMPLATE: &str = "{service}.{random4}";
+const DEFAULT_KEYED_SERVICE_TEMPLATE: &str = "{service}.{hmac8}";
+
+const CONSONANTS: &[&str] = &[
+    "b", "c", "d", "f", "g", "h", "j", "k", "l", "m", "n", "p", "r", "s", "t", "v", "w", "z", "br",
+    "ch", "dr", "fl", "gr", "kr", "pl", "sh", "st", "th", "tr",
+];
+const VOWELS: &[&str] = &["a", "e", "i", "o", "u", "ai", "ea", "io", "ou"];
+
+const ADJECTIVES: &[&str] = &[
+    "amber", "bold", "brave", "brisk", "calm", "clever", "cosmic", "crisp", "curious", "dapper",
+    "daring", "eager", "early", "fancy", "fierce", "fluffy", "gentle", "giddy", "golden", "grand",
+    "happy", "hidden", "humble", "icy", "jolly", "keen", "kind", "lively", "lucky", "lunar",
+    "mellow", "merry", "mighty", "misty", "noble", "odd", "plucky", "polite", "proud", "quick",
+    "quiet", "rapid", "rosy", "rustic", "shiny", "silent", "silver", "sleepy", "snowy", "solar",
+    "spicy", "steady", "stormy", "sunny", "swift", "tidy", "tiny", "vivid", "wary", "warm", "wild",
+    "wise", "witty", "zesty",
+];
+const ANIMALS: &[&str] = &[
+    "alpaca", "badger", "beaver", "bison", "camel", "cheetah", "cobra", "condor", "coyote",
+    "crane", "dingo", "dolphin", "eagle", "falcon", "ferret", "finch", "gecko", "gibbon", "heron",
+    "hippo", "ibex", "iguana", "jackal", "jaguar", "kestrel", "koala", "lemur", "llama", "lynx",
+    "magpie", "marmot", "mole", "moose", "narwhal", "newt", "ocelot", "orca", "osprey", "otter",
+    "owl", "panda", "parrot", "pelican", "puffin", "quail", "rabbit", "raven", "salmon", "seal",
+    "shark", "sloth", "sparrow", "squid", "stork", "tapir", "tiger", "toucan", "turtle", "viper",
+    "walrus", "weasel", "wombat", "yak", "zebra",
+];
+
+#[derive(clap::ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum AliasStyle {
+    /// Characters picked at random from the alphabet, e.g. `k3x9q2m7a1zt0c8w`
+    #[default]
+    Random,
+    /// Syllables that can be read out loud, e.g. `brokaseitu`
+    Pronounceable,
+    /// Words and a number, e.g. `brave-otter-42`
+    Words,
+    /// A random UUID, e.g. `0b7f3c1e-5a2d-4e8f-9c6b-1d2e3f4a5b6c`
+    Uuid,
+}
+
+/// How the local part of generated aliases looks. Unset options fall back to the ones in the
+/// config, then to the defaults.
+#[derive(clap::Args, Serialize, Deserialize, Clone, Debug, Default)]
+pub struct AliasOptions {
+    /// Style of the generated alias
+    #[arg(long, value_enum)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub style: Option<AliasStyle>,
+    /// Characters of `random` aliases, or syllables of `pronounceable` ones, or words of `words`
+    /// ones
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub length: Option<usize>,
+    /// Characters used by `random` aliases
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alphabet: Option<String>,
+    /// Text added before the generated alias
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+    /// Text added after the generated alias
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub suffix: Option<String>,
+    /// Service the alias is given to, e.g. `github.com`. The alias follows the template instead
+    /// of the style, and the service is used as the name of the rule.
+    #[arg(long = "for", value_name = "SERVICE", conflicts_with = "matcher")]
+    #[serde(skip)]
+    pub service: Option<String>,
+    /// Template of the aliases created with --for, with the `{service}`, `{randomN}` and
+    /// `{hmacN}` placeholders
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    /// Key of the `{hmacN}` placeholder. Only read from the config, as it's a secret.
+    #[arg(skip)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hmac_key: Option<String>,
+}
+
+impl AliasOptions {
+    /// Fills the unset options with the given defaults.
+    pub fn or(self, defaults: &AliasOptions) -> AliasOptions {
+        AliasOptions {
+            style: self.style.or(defaults.style),
+            length: self.length.or(defaults.length),
+            alphabet: self.alphabet.or_else(|| defaults.alphabet.clone()),
+            prefix: self.prefix.or_else(|| defaults.prefix.clone()),
+            suffix: self.suffix.or_else(|| defaults.suffix.clone()),
+            service: self.service,
+            template: self.template.or_else(|| defaults.template.clone()),
+            hmac_key: self.hmac_key.or_else(|| defaults.hmac_key.clone()),
+        }
+    }
+}
+
+/// Generates the local part of an alias.
+pub fn generate(options: &AliasOptions) -> anyhow::Result<String> {
+    let alias = generate_part(options)?;
+    check_local_part(&alias)?;
+    Ok(alias)
+}
+
+/// Generates an alias, or a part of one for the placeholders of a template.
+fn generate_part(options: &AliasOptions) -> anyhow::Result<String> {
+    if let Some(service) = &options.service {
+        return generate_for_service(options, service);
+    }
+
+    let mut rng = rand::rng();
+    let style = options.style.unwrap_or_default();
+
+    let alias = match style {
+        AliasStyle::Random => {
+            let alphabet = options
+                .alphabet
+                .as_deref()
+                .unwrap_or(DEFAULT_ALPHABET)
+                .chars()
+                .collect::<Vec<_>>();
+            if alphabet.is_empty() {
+                bail!("The alphabet can't be empty");
+            }
+
+            // every character is picked independently, so they can repeat
+            (0..length(options, 16)?)
+                .map(|_| alphabet.choose(&mut rng).unwrap())
+                .collect::<String>()
+        }
+        AliasStyle::Pronounceable => (0..length(options, 5)?)
+            .map(|_| {
+                let consonant = CONSONANTS.choose(&mut rng).unwrap();
+                let vowel = VOWELS.choose(&mut rng).unwrap();
+                format!("{consonant}{vowel}")
+            })
+            .collect::<String>(),
+        AliasStyle::Words => {
+            let adjectives = length(options, 2)? - 1;
+            let mut words = (0..adjectives)
+                .map(|_| *ADJECTIVES.choose(&mut rng).unwrap())
+                .collect::<Vec<_>>();
+            words.push(ANIMALS.choose(&mut rng).unwrap());
+            format!("{}-{}", words.join("-"), rng.random_range(10..100))
+        }
+        AliasStyle::Uuid => {
+            let mut bytes = rng.random::<[u8; 16]>();
+            // version 4, variant 1, as any other random UUID
+            bytes[6] = (bytes[6] & 0x0f) | 0x40;
+            bytes[8] = (bytes[8] & 0x3f) | 0x80;
+            let hex = bytes
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect::<String>();
+            format!(
+                "{}-{}-{}-{}-{}",
+                &hex[..8],
+                &hex[8..12],
+                &hex[12..16],
+                &hex[16..20],
+                &hex[20..]
+            )
+        }
+    };
+
+    finish(options, alias)
+}
+
+/// Placeholders are `{service}`, `{randomN}` for N random characters of the alphabet and
+/// `{hmacN}` for the first N hex digits of the HMAC-SHA256 of the service, which always gives
+/// the same alias for the same service and key.
+fn generate_for_service(options: &AliasOptions, service: &str) -> anyhow::Result<String> {
+    let service = normalize_service(service);
+    if service.is_empty() {
+        bail!("The service name must contain letters or digits");
+    }
+
+    let template = options
+        .template
+        .as_deref()
+        .unwrap_or(match options.hmac_key {
+            Some(_) => DEFAULT_KEYED_SERVICE_TEMPLATE,
+            None => DEFAULT_SERVICE_TEMPLATE,
+        });
+
+    let mut alias = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        alias.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('}') else {
+            bail!("Unclosed placeholder in the template {template:?}");
+        };
+        let placeholder = &rest[start + 1..start + end];
+        alias.push_str(&expand_placeholder(options, &service, placeholder)?);
+        rest = &rest[start + end + 1..];
+    }
+    alias.push_str(rest);
+
+    finish(options, alias)
+}
+
+fn expand_placeholder(
+    options: &AliasOptions,
+    service: &str,
+    placeholder: &str,
+) -> anyhow::Result<String> {
+    let count = |digits: &str, default| match digits {
+        "" => Ok(default),
+        digits => digits
+            .parse::<usize>()
+            .ok()
+            .filter(|count| *count > 0)
+            .ok_or_else(|| anyhow::anyhow!("Invalid placeholder {{{placeholder}}}")),
+    };
+
+    if placeholder == "service" {
+        Ok(service.to_string())
+    } else if let Some(digits) = placeholder.strip_prefix("random") {
+        let options = AliasOptions {
+            style: Some(AliasStyle::Random),
+            length: Some(count(digits, 4)?),
+            alphabet: options.alphabet.clone(),
+            ..AliasOptions::default()
+        };
+        generate_part(&options)
+    } else if let Some(digits) = placeholder.strip_prefix("hmac") {
+        let Some(key) = &options.hmac_key else {
+            bail!("The {{{placeholder}}} placeholder requires an hmac_key in the alias config");
+        };
+        let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes())?;
+        mac.update(service.as_bytes());
+        let hex = mac
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<String>();
+        let count = count(digits, 8)?.min(hex.len());
+        Ok(hex[..count].to_string())
+    } else {
+        bail!(
+            "Unknown placeholder {{{placeholder}}}, expected {{service}}, {{randomN}} or {{hmacN}}"
+        )
+    }
+}
+
+/// Lowercases the service and keeps the characters that can be in an email address, so
+/// `https://www.GitHub.com/` becomes `github.com`.
+pub fn normalize_service(service: &str) -> String {
+    let service = service.trim().to_lowercase();
+    let service = service
+        .split_once("://")
+        .map_or(service.as_str(), |(_, rest)| rest);
+    let service = service.split('/').next().unwrap_or_default();
+    let service = service.strip_prefix("www.").unwrap_or(service);
+
+    service
+        .chars()
+        .map(|char| match char {
+            'a'..='z' | '0'..='9' | '.' => char,
+            _ => '-',
+        })
+        .collect::<String>()
+        .trim_matches(['.', '-'])
+        .to_string()
+}
+
+fn finish(options: &AliasOptions, alias: String) -> anyhow::Result<String> {
+    let alias = format!(
+        "{}{alias}{}",
+        options.prefix.as_deref().unwrap_or_default(),
+        options.suffix.as_deref().unwrap_or_default()
+    );
+
+    if alias.chars().count() > MAX_LOCAL_PART_LENGTH {
+        bail!("The alias {alias:?} is longer than {MAX_LOCAL_PART_LENGTH} characters");
+    }
+
+    Ok(alias)
+}
+
+/// Only allows the characters that every provider accepts unquoted, as the alphabet, prefix,
+/// suffix and template come from the user and Cloudflare's errors about them are unclear.
+fn check_local_part(alias: &str) -> anyhow::Result<()> {
+    if let Some(invalid) = alias
+        .chars()
+        .find(|char| !char.is_ascii_alphanumeric() && !matches!(char, '.' | '_' | '+' | '-'))
+    {
+        bail!(
+            "The alias {alias:?} contains {invalid:?}, only letters, digits, '.', '_', '+' and '-' \
+             are allowed"
+        );
+    }
+    if alias.is_empty() {
+        bail!("The alias can't be empty");
+    }
+    if alias.starts_with('.') || alias.ends_with('.') || alias.contains("..") {
+        bail!("The alias {alias:?} can't start or end with a dot, or have two in a row");
+    }
+
+    Ok(())
+}
+
+fn length(options: &AliasOptions, default: usize) -> anyhow::Result<usize> {
+    match options.length.unwrap_or(default) {
+        0 => bail!("The length of the alias must be at least 1"),
+        length => Ok(length),
+    }
+}
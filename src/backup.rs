@@ -0,0 +1,85 @@
+use crate::cloudflare_api::{Address, EmailRoutingRule};
+use anyhow::{Context, bail};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Bumped whenever the backup format changes in a backwards incompatible way.
+pub const BACKUP_VERSION: u32 = 1;
+
+/// Snapshot of the rules of a zone and the destination addresses of its account.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Backup {
+    pub version: u32,
+    /// Name of the zone the rules were exported from.
+    pub zone: String,
+    #[serde(default)]
+    pub rules: Vec<EmailRoutingRule>,
+    #[serde(default)]
+    pub addresses: Vec<Address>,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum BackupFormat {
+    Json,
+    Toml,
+}
+
+impl BackupFormat {
+    /// Guesses the format from the file extension, defaulting to json.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) if extension.eq_ignore_ascii_case("toml") => BackupFormat::Toml,
+            _ => BackupFormat::Json,
+        }
+    }
+}
+
+pub fn write_backup(path: &Path, backup: &Backup, format: BackupFormat) -> anyhow::Result<()> {
+    let content = match format {
+        BackupFormat::Json => {
+            serde_json::to_string_pretty(backup).context("Failed to serialize backup")?
+        }
+        BackupFormat::Toml => toml::to_string(backup).context("Failed to serialize backup")?,
+    };
+
+    std::fs::write(path, content).with_context(|| format!("Failed to write backup at {path:?}"))
+}
+
+pub fn read_backup(path: &Path, format: BackupFormat) -> anyhow::Result<Backup> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read backup at {path:?}"))?;
+
+    let backup: Backup = match format {
+        BackupFormat::Json => serde_json::from_str(&content).map_err(anyhow::Error::from),
+        BackupFormat::Toml => toml::from_str(&content).map_err(anyhow::Error::from),
+    }
+    .with_context(|| format!("Failed to deserialize backup at {path:?}"))?;
+
+    if backup.version > BACKUP_VERSION {
+        bail!(
+            "Backup at {path:?} has version {}, but only versions up to {BACKUP_VERSION} are supported",
+            backup.version
+        );
+    }
+
+    Ok(backup)
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportStatus {
+    Created,
+    /// An identical rule or address already exists.
+    Skipped,
+    /// A rule with the same matcher but different settings already exists.
+    Conflict,
+    Failed,
+}
+
+/// Outcome of importing one rule or destination address.
+#[derive(Serialize, Debug)]
+pub struct ImportResult {
+    pub target: String,
+    pub status: ImportStatus,
+    pub details: String,
+}
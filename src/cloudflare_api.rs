@@ -1,108 +1,480 @@
 use anyhow::Context;
-use reqwest::{Method, RequestBuilder};
+use futures::{Stream, TryStreamExt, stream};
+use reqwest::{Method, RequestBuilder, StatusCode};
 use serde::de::DeserializeOwned;
-use serde::{Deserialize, Serialize, Serializer};
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
 use std::str::FromStr;
+use std::time::Duration;
 
 const API_BASE_URL: &str = "https://api.cloudflare.com/client/v4";
+/// The maximum page size accepted by all the list endpoints we use.
+const PAGE_SIZE: usize = 50;
+/// Delay before the first retry, doubled on every attempt.
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// Authentication error, returned when the credentials are invalid or lack a permission.
+pub const AUTHENTICATION_ERROR_CODE: usize = 10000;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Failed to reach the Cloudflare API")]
+    Transport(#[from] reqwest::Error),
+    #[error(
+        "Cloudflare API responded with {status}{}{}",
+        describe_errors(errors),
+        describe_retry_after(retry_after)
+    )]
+    Status {
+        status: StatusCode,
+        errors: Vec<RequestError>,
+        /// How long to wait before retrying, from the `Retry-After` header.
+        retry_after: Option<Duration>,
+    },
+    /// The request went through but Cloudflare reported it as unsuccessful.
+    #[error("Cloudflare API request failed{}", describe_errors(errors))]
+    Api { errors: Vec<RequestError> },
+    #[error("Couldn't parse the Cloudflare API response ({status})")]
+    Deserialize {
+        status: StatusCode,
+        source: serde_json::Error,
+    },
+    #[error("Cloudflare API response has no result")]
+    MissingResult,
+}
+
+impl Error {
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            Error::Transport(error) => error.status(),
+            Error::Status { status, .. } | Error::Deserialize { status, .. } => Some(*status),
+            Error::Api { .. } | Error::MissingResult => None,
+        }
+    }
+
+    /// The errors reported by Cloudflare, including the ones in their error chains.
+    pub fn request_errors(&self) -> Vec<&RequestError> {
+        let errors = match self {
+            Error::Status { errors, .. } | Error::Api { errors } => errors.as_slice(),
+            _ => &[],
+        };
+
+        let mut all_errors = vec![];
+        let mut pending = errors.iter().collect::<Vec<_>>();
+        while let Some(error) = pending.pop() {
+            all_errors.push(error);
+            pending.extend(&error.error_chain);
+        }
+        all_errors
+    }
+
+    pub fn has_code(&self, code: usize) -> bool {
+        self.request_errors().iter().any(|error| error.code == code)
+    }
+
+    /// Whether the credentials are invalid or don't have the permissions for the request.
+    pub fn is_authentication_error(&self) -> bool {
+        matches!(
+            self.status(),
+            Some(StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN)
+        ) || self.has_code(AUTHENTICATION_ERROR_CODE)
+    }
+}
+
+fn describe_errors(errors: &[RequestError]) -> String {
+    if errors.is_empty() {
+        return String::new();
+    }
+
+    let errors = errors
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("; ");
+    format!(": {errors}")
+}
+
+fn describe_retry_after(retry_after: &Option<Duration>) -> String {
+    match retry_after {
+        Some(retry_after) => format!(" (retry after {}s)", retry_after.as_secs()),
+        None => String::new(),
+    }
+}
+
+/// The `Retry-After` header, either a number of seconds or an HTTP date.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    // IMF-fixdate, e.g. `Wed, 21 Oct 2015 07:28:00 GMT`, is a subset of RFC 2822
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let delay = date.signed_duration_since(chrono::Utc::now());
+    Some(delay.to_std().unwrap_or_default())
+}
+
+/// Exponential backoff with full jitter, so concurrent clients don't retry in lockstep.
+fn backoff_delay(attempt: u32) -> Duration {
+    let max_delay = RETRY_BASE_DELAY
+        .saturating_mul(2u32.saturating_pow(attempt - 1))
+        .min(RETRY_MAX_DELAY);
+    max_delay.mul_f64(rand::random::<f64>())
+}
+
+/// Settings of the client that aren't credentials.
+#[derive(Debug, Clone)]
+pub struct ClientOptions {
+    /// Defaults to the Cloudflare API, can point to a mock server for testing.
+    pub base_url: String,
+    /// How many times a request is sent before giving up, including the first one.
+    pub max_attempts: u32,
+    /// Timeout of each attempt of a request.
+    pub timeout: Duration,
+    /// Also retry `POST` requests after server errors and timeouts, which may create duplicates.
+    /// Rate limited and unsent requests are always retried.
+    pub retry_non_idempotent: bool,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        ClientOptions {
+            base_url: API_BASE_URL.to_string(),
+            max_attempts: 4,
+            timeout: Duration::from_secs(30),
+            retry_non_idempotent: false,
+        }
+    }
+}
+
+/// The two ways of authenticating to the Cloudflare API.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum Credentials {
+    /// A scoped API token, sent as a bearer token.
+    ApiToken { api_token: String },
+    /// The account's email and its Global API Key, which has every permission.
+    GlobalApiKey { email: String, api_key: String },
+}
 
 pub struct Client {
     client: reqwest::Client,
-    email: String,
-    api_token: String,
-    api_key: String,
+    options: ClientOptions,
+    credentials: Credentials,
 }
 
 impl Client {
-    pub async fn new(email: String, api_token: String, api_key: String) -> anyhow::Result<Self> {
+    pub async fn new(credentials: Credentials, mut options: ClientOptions) -> anyhow::Result<Self> {
         let client = reqwest::Client::builder()
+            .timeout(options.timeout)
             .build()
             .context("Failed to create client")?;
 
+        options.base_url = options.base_url.trim_end_matches('/').to_string();
+
         Ok(Client {
             client,
-            email,
-            api_token,
-            api_key,
+            options,
+            credentials,
         })
     }
 
-    pub async fn verify_token(&self) -> anyhow::Result<Response<VerifyTokenResult>> {
-        let url = "/user/tokens/verify";
-        self.send_get(url).await
+    /// Checks that the credentials are valid, using the verification route of their kind.
+    pub async fn verify_credentials(&self) -> Result<VerifiedCredentials> {
+        match &self.credentials {
+            Credentials::ApiToken { .. } => {
+                let url = "/user/tokens/verify";
+                let token = self.send_get(url).await?.into_result()?;
+                Ok(VerifiedCredentials::ApiToken(token))
+            }
+            Credentials::GlobalApiKey { .. } => {
+                let url = "/user";
+                let user = self.send_get(url).await?.into_result()?;
+                Ok(VerifiedCredentials::GlobalApiKey(user))
+            }
+        }
     }
 
-    pub async fn list_zones(&self) -> anyhow::Result<Response<Vec<Zone>>> {
+    pub async fn list_zones(&self) -> Result<Vec<Zone>> {
         let url = "/zones";
-        self.send_get(url).await
+        self.get_all_pages(url).await
     }
 
-    pub async fn get_email_routing_settings(
-        &self,
-        zone_id: &str,
-    ) -> anyhow::Result<Response<EmailRoutingSettings>> {
+    pub async fn get_email_routing_settings(&self, zone_id: &str) -> Result<EmailRoutingSettings> {
         let url = format!("/zones/{zone_id}/email/routing");
-        self.send_get(&url).await
+        self.send_get(&url).await?.into_result()
     }
 
-    pub async fn list_email_routing_rules(
-        &self,
-        zone_id: &str,
-    ) -> anyhow::Result<Response<Vec<EmailRoutingRule>>> {
+    pub async fn enable_email_routing(&self, zone_id: &str) -> Result<EmailRoutingSettings> {
+        let url = format!("/zones/{zone_id}/email/routing/enable");
+        self.send(Method::POST, &url, &serde_json::Map::new())
+            .await?
+            .into_result()
+    }
+
+    pub async fn disable_email_routing(&self, zone_id: &str) -> Result<EmailRoutingSettings> {
+        let url = format!("/zones/{zone_id}/email/routing/disable");
+        self.send(Method::POST, &url, &serde_json::Map::new())
+            .await?
+            .into_result()
+    }
+
+    /// The DNS records required by email routing. Whether they're set up is only known from the
+    /// status of the email routing settings, not per record.
+    pub async fn get_email_routing_dns_records(&self, zone_id: &str) -> Result<Vec<DnsRecord>> {
+        let url = format!("/zones/{zone_id}/email/routing/dns");
+        self.send_get(&url).await?.into_result()
+    }
+
+    pub async fn list_email_routing_rules(&self, zone_id: &str) -> Result<Vec<EmailRoutingRule>> {
         let url = format!("/zones/{zone_id}/email/routing/rules");
-        self.send_get(&url).await
+        self.get_all_pages(&url).await
     }
 
     pub async fn create_routing_rule(
         &self,
         zone_id: &str,
         rule: &CreateRoutingRuleRequest,
-    ) -> anyhow::Result<Response<EmailRoutingRule>> {
+    ) -> Result<EmailRoutingRule> {
         let url = format!("/zones/{zone_id}/email/routing/rules");
-        self.send(Method::POST, &url, rule).await
+        self.send(Method::POST, &url, rule).await?.into_result()
     }
 
-    pub async fn list_destination_addresses(
+    pub async fn get_routing_rule(
+        &self,
+        zone_id: &str,
+        rule_identifier: &str,
+    ) -> Result<EmailRoutingRule> {
+        let url = format!("/zones/{zone_id}/email/routing/rules/{rule_identifier}");
+        self.send_get(&url).await?.into_result()
+    }
+
+    pub async fn update_routing_rule(
+        &self,
+        zone_id: &str,
+        rule_identifier: &str,
+        rule: &UpdateRoutingRuleRequest,
+    ) -> Result<EmailRoutingRule> {
+        let url = format!("/zones/{zone_id}/email/routing/rules/{rule_identifier}");
+        self.send(Method::PUT, &url, rule).await?.into_result()
+    }
+
+    pub async fn get_catch_all_rule(&self, zone_id: &str) -> Result<EmailRoutingRule> {
+        let url = format!("/zones/{zone_id}/email/routing/rules/catch_all");
+        self.send_get(&url).await?.into_result()
+    }
+
+    pub async fn update_catch_all_rule(
+        &self,
+        zone_id: &str,
+        rule: &UpdateCatchAllRuleRequest,
+    ) -> Result<EmailRoutingRule> {
+        let url = format!("/zones/{zone_id}/email/routing/rules/catch_all");
+        self.send(Method::PUT, &url, rule).await?.into_result()
+    }
+
+    pub async fn list_destination_addresses(&self, account_id: &str) -> Result<Vec<Address>> {
+        let url = format!("/accounts/{account_id}/email/routing/addresses");
+        self.get_all_pages(&url).await
+    }
+
+    pub async fn create_destination_address(
         &self,
         account_id: &str,
-    ) -> anyhow::Result<Response<Vec<Address>>> {
+        email: &str,
+    ) -> Result<Address> {
         let url = format!("/accounts/{account_id}/email/routing/addresses");
-        self.send_get(&url).await
+        self.send(Method::POST, &url, &CreateAddressRequest { email })
+            .await?
+            .into_result()
+    }
+
+    pub async fn get_destination_address(
+        &self,
+        account_id: &str,
+        address_identifier: &str,
+    ) -> Result<Address> {
+        let url = format!("/accounts/{account_id}/email/routing/addresses/{address_identifier}");
+        self.send_get(&url).await?.into_result()
+    }
+
+    /// Returns the deleted address, when the api includes it in the response.
+    pub async fn delete_destination_address(
+        &self,
+        account_id: &str,
+        address_identifier: &str,
+    ) -> Result<Option<Address>> {
+        let url = format!("/accounts/{account_id}/email/routing/addresses/{address_identifier}");
+        Ok(self.send(Method::DELETE, &url, &()).await?.result)
     }
 
+    /// Returns the deleted rule, when the api includes it in the response.
     pub async fn delete_routing_rule(
         &self,
         zone_id: &str,
         rule_identifier: &str,
-    ) -> anyhow::Result<Response<EmailRoutingRule>> {
+    ) -> Result<Option<EmailRoutingRule>> {
         let url = format!("/zones/{zone_id}/email/routing/rules/{rule_identifier}");
-        self.send(Method::DELETE, &url, &()).await
+        Ok(self.send(Method::DELETE, &url, &()).await?.result)
+    }
+
+    /// Fetches every page of a paginated list endpoint and collects all the items.
+    pub async fn get_all_pages<T: DeserializeOwned>(&self, url: &str) -> Result<Vec<T>> {
+        self.paginate(url).try_collect().await
+    }
+
+    /// Streams the items of a paginated list endpoint, only fetching the next page once the
+    /// items of the previous one have been consumed.
+    pub fn paginate<'a, T: DeserializeOwned + 'a>(
+        &'a self,
+        url: &'a str,
+    ) -> impl Stream<Item = Result<T>> + 'a {
+        stream::try_unfold(Some(1), move |page| async move {
+            let Some(page) = page else {
+                return Ok::<_, Error>(None);
+            };
+
+            let separator = if url.contains('?') { '&' } else { '?' };
+            let page_url = format!("{url}{separator}page={page}&per_page={PAGE_SIZE}");
+            let response: Response<Vec<T>> = self.send_get(&page_url).await?;
+            let result_info = response.result_info;
+            let items = response.result.ok_or(Error::MissingResult)?;
+
+            let next_page = result_info
+                .filter(|info| !items.is_empty() && info.has_next_page())
+                .map(|info| info.page + 1);
+
+            Ok(Some((stream::iter(items.into_iter().map(Ok)), next_page)))
+        })
+        .try_flatten()
     }
 
-    async fn send_get<T: DeserializeOwned>(&self, url: &str) -> anyhow::Result<Response<T>> {
+    async fn send_get<T: DeserializeOwned>(&self, url: &str) -> Result<Response<T>> {
         self.send(Method::GET, url, &()).await
     }
 
+    /// Sends a request, retrying with exponential backoff when rate limited or after transient
+    /// failures.
     async fn send<B: Serialize, T: DeserializeOwned>(
         &self,
         method: Method,
         url: &str,
         body: &B,
-    ) -> anyhow::Result<Response<T>> {
-        self.add_auth_headers(self.client.request(method, format!("{API_BASE_URL}{url}")))
+    ) -> Result<Response<T>> {
+        let mut attempt = 1;
+        loop {
+            let error = match self.send_once(method.clone(), url, body).await {
+                Ok(response) => return Ok(response),
+                Err(error) => error,
+            };
+
+            if attempt >= self.options.max_attempts || !self.should_retry(&method, &error) {
+                return Err(error);
+            }
+
+            let delay = match &error {
+                // retrying any earlier would be rejected again, and waiting for minutes would
+                // hang the command, so give up with the wait in the error
+                Error::Status {
+                    retry_after: Some(retry_after),
+                    ..
+                } if *retry_after > RETRY_MAX_DELAY => return Err(error),
+                Error::Status {
+                    retry_after: Some(retry_after),
+                    ..
+                } => *retry_after,
+                _ => backoff_delay(attempt),
+            };
+
+            eprintln!(
+                "{error}. Retrying in {:.1}s (attempt {}/{})...",
+                delay.as_secs_f32(),
+                attempt + 1,
+                self.options.max_attempts
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    fn should_retry(&self, method: &Method, error: &Error) -> bool {
+        let idempotent = self.options.retry_non_idempotent || *method != Method::POST;
+
+        match error {
+            // the request wasn't processed, so it's safe to send it again
+            Error::Status {
+                status: StatusCode::TOO_MANY_REQUESTS,
+                ..
+            } => true,
+            Error::Transport(error) if error.is_connect() => true,
+            Error::Transport(error) => idempotent && error.is_timeout(),
+            Error::Status { status, .. } => idempotent && status.is_server_error(),
+            _ => false,
+        }
+    }
+
+    async fn send_once<B: Serialize, T: DeserializeOwned>(
+        &self,
+        method: Method,
+        url: &str,
+        body: &B,
+    ) -> Result<Response<T>> {
+        let response = self
+            .add_auth_headers(
+                self.client
+                    .request(method, format!("{}{url}", self.options.base_url)),
+            )
             .json(body)
             .send()
-            .await?
-            .json::<Response<T>>()
-            .await
-            .context("Couldn't parse json response")
+            .await?;
+
+        let status = response.status();
+        let retry_after = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_retry_after);
+        let body = response.bytes().await?;
+
+        let response = match serde_json::from_slice::<Response<T>>(&body) {
+            Ok(response) => response,
+            // error pages of proxies and load balancers aren't json
+            Err(_) if !status.is_success() => {
+                return Err(Error::Status {
+                    status,
+                    errors: vec![],
+                    retry_after,
+                });
+            }
+            Err(source) => return Err(Error::Deserialize { status, source }),
+        };
+
+        if !status.is_success() {
+            return Err(Error::Status {
+                status,
+                errors: response.errors,
+                retry_after,
+            });
+        }
+        if !response.success {
+            return Err(Error::Api {
+                errors: response.errors,
+            });
+        }
+
+        Ok(response)
     }
 
     fn add_auth_headers(&self, request_builder: RequestBuilder) -> RequestBuilder {
-        request_builder
-            .header("Authorization", format!("Bearer {}", &self.api_token))
-            .header("X-Auth-Email", &self.email)
-            .header("X-Auth-Key", &self.api_key)
+        match &self.credentials {
+            Credentials::ApiToken { api_token } => request_builder.bearer_auth(api_token),
+            Credentials::GlobalApiKey { email, api_key } => request_builder
+                .header("X-Auth-Email", email)
+                .header("X-Auth-Key", api_key),
+        }
     }
 }
 
@@ -115,6 +487,34 @@ pub struct Response<R> {
     pub messages: Vec<ResponseInfo>,
     pub success: bool,
     pub result: Option<R>,
+    pub result_info: Option<ResultInfo>,
+}
+
+impl<R> Response<R> {
+    pub fn into_result(self) -> Result<R> {
+        self.result.ok_or(Error::MissingResult)
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Deserialize, Debug)]
+pub struct ResultInfo {
+    pub page: usize,
+    pub per_page: usize,
+    pub count: usize,
+    /// Not every endpoint reports it, in that case it can be derived from `total_count`.
+    pub total_pages: Option<usize>,
+    pub total_count: Option<usize>,
+}
+
+impl ResultInfo {
+    pub fn has_next_page(&self) -> bool {
+        match (self.total_pages, self.total_count) {
+            (Some(total_pages), _) => self.page < total_pages,
+            (None, Some(total_count)) => self.page * self.per_page < total_count,
+            (None, None) => self.count >= self.per_page,
+        }
+    }
 }
 
 #[allow(dead_code)]
@@ -133,6 +533,16 @@ pub struct RequestError {
     pub error_chain: Vec<RequestError>,
 }
 
+impl Display for RequestError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (code {})", self.message, self.code)?;
+        for cause in &self.error_chain {
+            write!(f, ", caused by: {cause}")?;
+        }
+        Ok(())
+    }
+}
+
 #[allow(dead_code)]
 #[derive(Deserialize, Debug)]
 pub struct VerifyTokenResult {
@@ -142,6 +552,18 @@ pub struct VerifyTokenResult {
     pub not_before: Option<String>,
 }
 
+#[allow(dead_code)]
+#[derive(Deserialize, Debug)]
+pub struct User {
+    pub id: String,
+    pub email: String,
+}
+
+pub enum VerifiedCredentials {
+    ApiToken(VerifyTokenResult),
+    GlobalApiKey(User),
+}
+
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "lowercase")]
 pub enum TokenStatus {
@@ -150,8 +572,7 @@ pub enum TokenStatus {
     Expired,
 }
 
-#[allow(dead_code)]
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct EmailRoutingSettings {
     pub id: String,
     pub enabled: bool,
@@ -161,7 +582,7 @@ pub struct EmailRoutingSettings {
     pub status: Option<EmailRoutingStatus>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "lowercase")]
 pub enum EmailRoutingStatus {
     Ready,
@@ -172,19 +593,30 @@ pub enum EmailRoutingStatus {
     Unlocked,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DnsRecord {
+    #[serde(rename = "type")]
+    pub record_type: String,
+    pub name: String,
+    pub content: String,
+    pub priority: Option<u32>,
+    pub ttl: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
 pub struct Zone {
     pub id: String,
+    pub name: String,
     pub account: ZoneAccount,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct ZoneAccount {
     pub id: String,
     pub name: String,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct EmailRoutingRule {
     pub id: String,
     #[serde(default)]
@@ -194,15 +626,36 @@ pub struct EmailRoutingRule {
     pub matchers: Vec<EmailRoutingRuleMatcher>,
     pub name: Option<String>,
     pub priority: Option<usize>,
+    /// Deprecated identifier of the rule, the same as its id.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+}
+
+impl EmailRoutingRule {
+    /// The email addresses matched by the rule.
+    pub fn literal_matchers(&self) -> impl Iterator<Item = &str> {
+        self.matchers
+            .iter()
+            .filter_map(|matcher| match &matcher.matcher_type {
+                EmailRoutingRuleMatcherType::All => None,
+                EmailRoutingRuleMatcherType::Literal { value, .. } => Some(value.as_str()),
+            })
+    }
+
+    pub fn is_catch_all(&self) -> bool {
+        self.matchers
+            .iter()
+            .any(|matcher| matches!(matcher.matcher_type, EmailRoutingRuleMatcherType::All))
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct EmailRoutingRuleAction {
     #[serde(flatten)]
     pub action_type: EmailRoutingRuleActionType,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 #[serde(tag = "type")]
 pub enum EmailRoutingRuleActionType {
@@ -215,6 +668,14 @@ impl FromStr for EmailRoutingRuleAction {
     type Err = String;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(worker) = s.strip_prefix("worker:") {
+            return Ok(EmailRoutingRuleAction {
+                action_type: EmailRoutingRuleActionType::Worker {
+                    value: vec![worker.to_string()],
+                },
+            });
+        }
+
         match s {
             "drop" => Ok(EmailRoutingRuleAction {
                 action_type: EmailRoutingRuleActionType::Drop,
@@ -228,7 +689,7 @@ impl FromStr for EmailRoutingRuleAction {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct EmailRoutingRuleMatcher {
     #[serde(flatten)]
     pub matcher_type: EmailRoutingRuleMatcherType,
@@ -242,38 +703,50 @@ impl FromStr for EmailRoutingRuleMatcher {
             "*" => Ok(EmailRoutingRuleMatcher {
                 matcher_type: EmailRoutingRuleMatcherType::All,
             }),
-            _ => Ok(EmailRoutingRuleMatcher {
-                matcher_type: EmailRoutingRuleMatcherType::Literal {
-                    value: s.to_string(),
-                },
-            }),
+            _ => Ok(EmailRoutingRuleMatcher::literal(s.to_string())),
+        }
+    }
+}
+
+impl EmailRoutingRuleMatcher {
+    /// Matches the recipient of the email, the only field the API supports matching.
+    pub fn literal(value: String) -> Self {
+        EmailRoutingRuleMatcher {
+            matcher_type: EmailRoutingRuleMatcherType::Literal {
+                field: default_literal_field(),
+                value,
+            },
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 #[serde(tag = "type")]
 pub enum EmailRoutingRuleMatcherType {
     All,
-    #[serde(serialize_with = "serialize_literal")]
     Literal {
+        /// The field of the email the value is compared to, as given by the API.
+        #[serde(default = "default_literal_field")]
+        field: String,
         value: String,
     },
 }
 
-fn serialize_literal<S>(value: &String, serializer: S) -> Result<S::Ok, S::Error>
-where
-    S: Serializer,
-{
-    use serde::ser::SerializeStruct;
-    let mut lit = serializer.serialize_struct("Literal", 2)?;
-    lit.serialize_field("value", value)?;
-    lit.serialize_field("field", "to")?;
-    lit.end()
+impl EmailRoutingRuleMatcherType {
+    pub fn field(&self) -> Option<&str> {
+        match self {
+            EmailRoutingRuleMatcherType::All => None,
+            EmailRoutingRuleMatcherType::Literal { field, .. } => Some(field),
+        }
+    }
+}
+
+fn default_literal_field() -> String {
+    "to".to_string()
 }
 
-#[derive(Serialize, Debug, Default)]
+#[derive(Serialize, Debug, Default, Clone)]
 pub struct CreateRoutingRuleRequest {
     pub actions: Vec<EmailRoutingRuleAction>,
     pub matchers: Vec<EmailRoutingRuleMatcher>,
@@ -285,6 +758,30 @@ pub struct CreateRoutingRuleRequest {
     pub priority: Option<usize>,
 }
 
+impl From<EmailRoutingRule> for CreateRoutingRuleRequest {
+    fn from(rule: EmailRoutingRule) -> Self {
+        CreateRoutingRuleRequest {
+            actions: rule.actions,
+            matchers: rule.matchers,
+            enabled: Some(rule.enabled),
+            name: rule.name,
+            priority: rule.priority,
+        }
+    }
+}
+
+/// Updating a rule replaces it entirely, so it takes the same body as creating one.
+pub type UpdateRoutingRuleRequest = CreateRoutingRuleRequest;
+
+/// The catch-all rule takes the same body as the other rules, but has no priority and its only
+/// matcher must be [`EmailRoutingRuleMatcherType::All`].
+pub type UpdateCatchAllRuleRequest = CreateRoutingRuleRequest;
+
+#[derive(Serialize, Debug)]
+struct CreateAddressRequest<'a> {
+    email: &'a str,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Address {
     pub id: Option<String>,
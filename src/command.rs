@@ -1,146 +1,430 @@
+use crate::CreateArgs;
+use crate::GlobalArgs;
+use crate::ListArgs;
+use crate::alias;
+use crate::backup;
 use crate::cloudflare_api;
 use crate::cloudflare_api::EmailRoutingRuleMatcherType;
 use crate::config;
-use anyhow::{bail, Context};
+use crate::filter;
+use crate::manifest;
+use crate::output;
+use crate::secrets;
+use crate::state;
+use anyhow::{Context, bail};
+use chrono::{DateTime, SecondsFormat, Utc};
 use cloudflare_api::EmailRoutingRuleMatcher;
-use rand::prelude::IteratorRandom;
-use std::cmp::Reverse;
-
-pub async fn handle_setup(email: String, api_token: String, api_key: String) -> anyhow::Result<()> {
-    let config_path = config::get_config_path()?;
-    let config = config::ClientConfig {
-        email,
-        api_token,
-        api_key,
-    };
+use futures::StreamExt;
+use serde::Serialize;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+
+pub async fn handle_setup(
+    global: &GlobalArgs,
+    credentials: cloudflare_api::Credentials,
+    encrypt: bool,
+) -> anyhow::Result<()> {
+    let mut config = config::load_config()?;
+    let profile = config.profile_name(global.profile.as_deref()).to_string();
 
     let client = cloudflare_api::Client::new(
-        config.email.clone(),
-        config.api_token.clone(),
-        config.api_key.clone(),
+        credentials.clone(),
+        // the base url and retry settings of an existing profile still apply
+        config
+            .profiles
+            .get(&profile)
+            .map_or_else(config::default_client_options, |config| {
+                config.client_options()
+            }),
     )
     .await?;
 
-    println!("Verifying API token...");
-    let response = client.verify_token().await?;
+    eprintln!("Verifying credentials...");
+    let verified = client.verify_credentials().await.map_err(|error| {
+        if error.is_authentication_error() {
+            let message = match &credentials {
+                cloudflare_api::Credentials::ApiToken { .. } => "The API token is invalid",
+                cloudflare_api::Credentials::GlobalApiKey { .. } => {
+                    "The email or Global API Key is invalid"
+                }
+            };
+            anyhow::Error::new(error).context(message)
+        } else {
+            error.into()
+        }
+    })?;
 
-    if let Some(token) = response.result {
-        if matches!(token.status, cloudflare_api::TokenStatus::Active) {
-            println!(
+    match verified {
+        cloudflare_api::VerifiedCredentials::ApiToken(token) => {
+            if !matches!(token.status, cloudflare_api::TokenStatus::Active) {
+                bail!("Token is not active: {token:?}")
+            }
+            eprintln!(
                 "Token is valid (id: {:?}, status: {:?}, expires on: {})",
                 token.id,
                 token.status,
                 token.expires_on.unwrap_or("Never".to_string())
             );
-        } else {
-            bail!("Token is not active: {token:?}")
+        }
+        cloudflare_api::VerifiedCredentials::GlobalApiKey(user) => {
+            eprintln!("Global API Key is valid for {}", user.email);
+        }
+    }
+
+    let stored_credentials = if encrypt {
+        let passphrase = secrets::read_new_passphrase()?;
+        config::StoredCredentials::Encrypted {
+            encrypted_credentials: secrets::encrypt(&credentials, &passphrase)?,
         }
     } else {
-        bail!("Failed to verify token: {response:?}")
+        config::StoredCredentials::Plaintext(credentials)
+    };
+
+    // the first profile set up is the default one, whatever its name
+    if config.profiles.is_empty() && config.default_profile.is_none() {
+        config.default_profile = Some(profile.clone());
     }
 
-    let config_content = toml::to_string(&config).context("Failed to serialize config")?;
+    // keep the settings that aren't credentials if the profile was already set up
+    match config.profiles.get_mut(&profile) {
+        Some(client_config) => client_config.credentials = stored_credentials,
+        None => {
+            let client_config = config::ClientConfig::new(stored_credentials);
+            config.profiles.insert(profile.clone(), client_config);
+        }
+    }
 
-    std::fs::create_dir_all(config_path.parent().unwrap())
-        .context("Failed to create config directory")?;
+    let config_path = config::save_config(&config)?;
+    eprintln!("Profile {profile:?} saved at {}", config_path.display());
+
+    if !encrypt {
+        eprintln!();
+        eprintln!(
+            "WARNING: the credentials are stored in PLAINTEXT, only protected by the file permissions."
+        );
+        eprintln!(
+            "Anyone who can read {} can manage your email routing.",
+            config_path.display()
+        );
+        eprintln!("Run setup again with --encrypt to protect them with a passphrase.");
+    }
 
-    std::fs::write(&config_path, config_content)
-        .with_context(|| format!("Failed to write config at {config_path:?}"))?;
+    Ok(())
+}
 
-    // TODO: encrypt file with password?
-    // TODO: advise user that tokens are being stored in plaintext
-    println!("Config saved at {}", config_path.display());
+// Permissions of the API token, named as in the Cloudflare dashboard.
+const ZONE_PERMISSION: &str = "Zone: Zone (Edit)";
+const SETTINGS_PERMISSION: &str = "Zone: Zone Settings (Edit)";
+const RULES_PERMISSION: &str = "Zone: Email Routing Rules (Edit)";
+const ADDRESSES_PERMISSION: &str = "Account: Email Routing Addresses (Edit)";
+
+trait PermissionContext<T> {
+    /// Explains authentication errors as the API token lacking the given permission.
+    fn permission(self, permission: &str) -> anyhow::Result<T>;
+}
+
+impl<T> PermissionContext<T> for cloudflare_api::Result<T> {
+    fn permission(self, permission: &str) -> anyhow::Result<T> {
+        self.map_err(|error| {
+            if error.is_authentication_error() {
+                let hint = format!("The API token is invalid or lacks the {permission} permission");
+                anyhow::Error::new(error).context(hint)
+            } else {
+                error.into()
+            }
+        })
+    }
+}
+
+pub fn handle_lock() -> anyhow::Result<()> {
+    if secrets::lock()? {
+        eprintln!("Credentials locked, the passphrase will be asked again.");
+    } else {
+        eprintln!("The credentials weren't unlocked.");
+    }
+
+    Ok(())
+}
+
+pub fn handle_list_profiles(global: &GlobalArgs) -> anyhow::Result<()> {
+    let config = config::load_config()?;
+
+    if config.profiles.is_empty() {
+        eprintln!("No profiles found. Please run the setup command first.");
+    }
+
+    output::print_list(global.output, &config.summaries())?;
+
+    Ok(())
+}
+
+pub fn handle_use_profile(profile: String) -> anyhow::Result<()> {
+    let mut config = config::load_config()?;
+
+    if !config.profiles.contains_key(&profile) {
+        bail!("Profile {profile:?} not found");
+    }
+
+    eprintln!("Using profile {profile:?} by default");
+    config.default_profile = Some(profile);
+    config::save_config(&config)?;
 
     Ok(())
 }
 
-async fn create_cf_client_from_config() -> anyhow::Result<cloudflare_api::Client> {
-    let Some(config) = config::load_config()? else {
-        bail!("No config found. Please run the setup command first.");
+pub fn handle_remove_profile(profile: String) -> anyhow::Result<()> {
+    let mut config = config::load_config()?;
+
+    if config.profiles.remove(&profile).is_none() {
+        bail!("Profile {profile:?} not found");
+    }
+    if config.default_profile.as_ref() == Some(&profile) {
+        config.default_profile = None;
+        eprintln!("Removed the default profile, select another one with profile use");
+    }
+
+    config::save_config(&config)?;
+    eprintln!("Profile {profile:?} removed");
+
+    Ok(())
+}
+
+fn load_existing_config(global: &GlobalArgs) -> anyhow::Result<config::ClientConfig> {
+    let Some(config) = config::load_profile(global.profile.as_deref())? else {
+        bail!(
+            "No config found. Please run the setup command first or set {}.",
+            config::API_TOKEN_ENV
+        );
     };
 
-    let client =
-        cloudflare_api::Client::new(config.email, config.api_token, config.api_key).await?;
+    Ok(config)
+}
 
-    Ok(client)
+async fn create_cf_client(config: &config::ClientConfig) -> anyhow::Result<cloudflare_api::Client> {
+    cloudflare_api::Client::new(config.credentials()?, config.client_options()).await
 }
 
-async fn select_first_zone(
+/// Loads the config, creates a client and selects the zone to operate on.
+///
+/// The zone given with `--zone` takes precedence over the default zone saved in the config.
+async fn connect(
+    global: &GlobalArgs,
+) -> anyhow::Result<(cloudflare_api::Client, cloudflare_api::Zone)> {
+    let config = load_existing_config(global)?;
+    let client = create_cf_client(&config).await?;
+
+    let zone_identifier = global.zone.as_deref().or(config.default_zone.as_deref());
+    let zone = select_zone(&client, zone_identifier).await?;
+
+    Ok((client, zone))
+}
+
+fn zone_matches(zone: &cloudflare_api::Zone, identifier: &str) -> bool {
+    zone.id == identifier || zone.name.eq_ignore_ascii_case(identifier)
+}
+
+/// The error with its causes, for the rows of a table where it can't be returned.
+fn describe_error(error: cloudflare_api::Error) -> String {
+    format!("{:#}", anyhow::Error::from(error))
+}
+
+fn format_list<T: std::fmt::Display>(items: impl IntoIterator<Item = T>) -> String {
+    items
+        .into_iter()
+        .map(|item| format!("  - {item}"))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+async fn select_zone(
     client: &cloudflare_api::Client,
+    zone_identifier: Option<&str>,
 ) -> anyhow::Result<cloudflare_api::Zone> {
-    // TODO: be able for user to select zone
-    let zone = client
-        .list_zones()
-        .await?
-        .result
-        .map(|mut zones| zones.pop())
-        .flatten()
-        .context("No zone found")?;
+    let mut zones = client.list_zones().await.permission(ZONE_PERMISSION)?;
+
+    let zone = match zone_identifier {
+        Some(identifier) => {
+            let Some(index) = zones.iter().position(|zone| zone_matches(zone, identifier)) else {
+                bail!(
+                    "No zone found with name or id {identifier}. Available zones:\n{}",
+                    format_list(&zones)
+                )
+            };
+            zones.swap_remove(index)
+        }
+        None => match zones.len() {
+            0 => bail!("No zone found"),
+            1 => zones.remove(0),
+            _ => bail!(
+                "Multiple zones found. Select one with --zone <name|id> \
+                 or save a default with `zones --set-default <name|id>`:\n{}",
+                format_list(&zones)
+            ),
+        },
+    };
 
-    println!("Selected zone: {zone}");
+    eprintln!("Selected zone: {zone}");
 
     Ok(zone)
 }
 
-pub async fn handle_list_rules() -> anyhow::Result<()> {
-    let client = create_cf_client_from_config().await?;
+pub async fn handle_list_rules(global: &GlobalArgs, args: ListArgs) -> anyhow::Result<()> {
+    let pattern = |pattern: Option<String>| {
+        pattern
+            .map(|pattern| filter::Pattern::new(&pattern, args.regex))
+            .transpose()
+    };
+    let rule_filter = filter::RuleFilter {
+        matcher: pattern(args.matcher)?,
+        destination: pattern(args.destination)?,
+        action: args.action,
+        enabled: args.enabled,
+        name: pattern(args.name)?,
+    };
 
-    let zone = select_first_zone(&client).await?;
+    let (client, zone) = connect(global).await?;
+
+    let mut rules = client
+        .list_email_routing_rules(&zone.id)
+        .await
+        .permission(RULES_PERMISSION)?;
+
+    let state = reconcile_state(&zone, &rules)?;
+    rules.retain(|rule| {
+        rule_filter.matches(rule)
+            && args.tag.as_ref().is_none_or(|tag| {
+                state
+                    .rules
+                    .get(&rule.id)
+                    .is_some_and(|rule_state| rule_state.has_tag(tag))
+            })
+    });
+
+    if rules.is_empty() {
+        eprintln!("No rules found.");
+    }
 
-    let response = client.list_email_routing_rules(&zone.id).await?;
-    if let Some(mut rules) = response.result {
-        if rules.is_empty() {
-            println!("No rules found.");
-        } else {
-            println!("Rules:");
-            rules.sort_by_key(|rule| Reverse(rule.priority.unwrap_or(0)));
-            for rule in rules {
-                println!("  - {rule}");
-            }
-        }
-    } else {
-        bail!("Failed to list rules: {response:?}")
+    filter::sort_rules(&mut rules, args.sort);
+    if let Some(limit) = args.limit {
+        rules.truncate(limit);
     }
+    output::print_list(global.output, &rules)?;
 
     Ok(())
 }
 
+/// Loads the state, updating it to match the rules of the zone, which may have been changed
+/// outside the tool.
+fn reconcile_state(
+    zone: &cloudflare_api::Zone,
+    rules: &[cloudflare_api::EmailRoutingRule],
+) -> anyhow::Result<state::State> {
+    let mut state = state::load_state()?;
+    let reconciliation = state.reconcile(&zone.id, rules);
+
+    if !reconciliation.forgotten.is_empty() {
+        eprintln!(
+            "Forgot the metadata of rules deleted outside the tool: {}",
+            reconciliation.forgotten.join(", ")
+        );
+    }
+    if !reconciliation.renamed.is_empty() {
+        eprintln!(
+            "Updated the metadata of rules changed outside the tool: {}",
+            reconciliation.renamed.join(", ")
+        );
+    }
+    if !reconciliation.forgotten.is_empty() || !reconciliation.renamed.is_empty() {
+        state::save_state(&state)?;
+    }
+
+    Ok(state)
+}
+
 async fn get_email_domain(
     client: &cloudflare_api::Client,
     zone_id: &str,
 ) -> anyhow::Result<String> {
-    println!("No domain specified. Fetching it from the zone...");
+    eprintln!("No domain specified. Fetching it from the zone...");
 
     let settings = client
         .get_email_routing_settings(zone_id)
-        .await?
-        .result
+        .await
+        .permission(SETTINGS_PERMISSION)
         .context("Failed to get email routing settings")?;
 
     let domain = settings.name;
-    println!("Found domain: {domain}");
+    eprintln!("Found domain: {domain}");
 
     Ok(domain)
 }
 
-pub async fn handle_create_rule(
-    matcher: Option<EmailRoutingRuleMatcher>,
-    action: Option<cloudflare_api::EmailRoutingRuleAction>,
-    name: Option<String>,
-    priority: Option<usize>,
-) -> anyhow::Result<()> {
-    let client = create_cf_client_from_config().await?;
+/// Appends the zone's domain to matchers that only specify the email's username.
+async fn complete_matcher(
+    client: &cloudflare_api::Client,
+    zone: &cloudflare_api::Zone,
+    matcher: EmailRoutingRuleMatcher,
+) -> anyhow::Result<EmailRoutingRuleMatcher> {
+    match &matcher.matcher_type {
+        EmailRoutingRuleMatcherType::All => Ok(matcher),
+        EmailRoutingRuleMatcherType::Literal { value, .. } if value.contains("@") => {
+            // TODO: maybe better email validation?
+            Ok(matcher)
+        }
+        EmailRoutingRuleMatcherType::Literal { value, .. } => {
+            // if there is no @, we assume the user just inputted the email's username
+            // cloudflare needs us to specify the domain as well, so fetch it
+            let domain = get_email_domain(client, &zone.id).await?;
+
+            Ok(EmailRoutingRuleMatcher::literal(format!(
+                "{value}@{domain}"
+            )))
+        }
+    }
+}
+
+/// How many usernames are generated before giving up on finding one that isn't used yet.
+const MAX_ALIAS_ATTEMPTS: usize = 10;
 
-    let zone = select_first_zone(&client).await?;
+pub async fn handle_create_rule(global: &GlobalArgs, args: CreateArgs) -> anyhow::Result<()> {
+    let CreateArgs {
+        matcher,
+        action,
+        name,
+        priority,
+        disabled,
+        force,
+        ttl,
+        tags,
+        note,
+        alias: alias_options,
+    } = args;
+    let (client, zone) = connect(global).await?;
+
+    let expires_at = ttl
+        .map(|ttl| {
+            chrono::TimeDelta::from_std(ttl)
+                .ok()
+                .and_then(|ttl| Utc::now().checked_add_signed(ttl))
+                .context("The TTL is too long")
+        })
+        .transpose()?;
+
+    // the service the alias was given to, so leaks can be traced back to it
+    let site = alias_options
+        .service
+        .as_deref()
+        .map(alias::normalize_service);
+    let name = name.or_else(|| site.clone());
 
     let action = match action {
         Some(action) => action,
         None => {
             // Select first address
-            let addresses = client.list_destination_addresses(&zone.account.id).await?;
-            let Some(mut addresses) = addresses.result else {
-                bail!("Failed to list addresses: {addresses:?}")
-            };
+            let mut addresses = client
+                .list_destination_addresses(&zone.account.id)
+                .await
+                .permission(ADDRESSES_PERMISSION)?;
 
             let Some(address) = addresses.pop() else {
                 bail!("No addresses found to redirect. Please create or specify one.")
@@ -158,177 +442,1309 @@ pub async fn handle_create_rule(
         }
     };
 
+    let is_catch_all = matcher
+        .as_ref()
+        .is_some_and(|matcher| matches!(matcher.matcher_type, EmailRoutingRuleMatcherType::All));
+    if is_catch_all && expires_at.is_some() {
+        bail!("The catch-all rule can't expire, disable it with catch-all disable instead.");
+    }
+    if is_catch_all && (!tags.is_empty() || note.is_some()) {
+        bail!("The catch-all rule can't have tags or a note.");
+    }
+    let existing_rules = if is_catch_all {
+        vec![]
+    } else {
+        client
+            .list_email_routing_rules(&zone.id)
+            .await
+            .permission(RULES_PERMISSION)
+            .context("Failed to list rules")?
+    };
+
     let matcher = match matcher {
-        Some(matcher) => {
-            match &matcher.matcher_type {
-                EmailRoutingRuleMatcherType::All => matcher,
-                EmailRoutingRuleMatcherType::Literal { value } if value.contains("@") => {
-                    // TODO: maybe better email validation?
-                    matcher
-                }
-                EmailRoutingRuleMatcherType::Literal { value } => {
-                    // if there is no @, we assume the user just inputted the email's username
-                    // cloudflare needs us to specify the domain as well, so fetch it
-                    let domain = get_email_domain(&client, &zone.id).await?;
-
-                    EmailRoutingRuleMatcher {
-                        matcher_type: EmailRoutingRuleMatcherType::Literal {
-                            value: format!("{value}@{domain}"),
-                        },
-                    }
-                }
-            }
-        }
+        Some(matcher) => complete_matcher(&client, &zone, matcher).await?,
         None => {
             let domain = get_email_domain(&client, &zone.id).await?;
 
-            let random_username = "abcdefghijklmnopqrstuvwxyz0123456789"
-                .chars()
-                .choose_multiple(&mut rand::rng(), 16)
-                .into_iter()
-                .collect::<String>();
+            let alias_defaults = load_existing_config(global)?.alias.unwrap_or_default();
+            let alias_options = alias_options.or(&alias_defaults);
+            let mut username = alias::generate(&alias_options)?;
 
-            println!("No matcher specified. Generated random username: {random_username}");
+            for _ in 1..MAX_ALIAS_ATTEMPTS {
+                if find_literal_rule(&existing_rules, &format!("{username}@{domain}")).is_none() {
+                    break;
+                }
+                let regenerated = alias::generate(&alias_options)?;
+                // usernames without random parts are always the same, e.g. with an HMAC
+                if regenerated == username {
+                    break;
+                }
+                eprintln!("Username {username} is already used, generating another one...");
+                username = regenerated;
+            }
 
-            EmailRoutingRuleMatcher {
-                matcher_type: EmailRoutingRuleMatcherType::Literal {
-                    value: format!("{random_username}@{domain}"),
-                },
+            match &alias_options.service {
+                Some(service) => eprintln!(
+                    "Generated username for {}: {username}",
+                    alias::normalize_service(service)
+                ),
+                None => {
+                    eprintln!("No matcher specified. Generated random username: {username}")
+                }
             }
+
+            EmailRoutingRuleMatcher::literal(format!("{username}@{domain}"))
         }
     };
 
+    if matches!(matcher.matcher_type, EmailRoutingRuleMatcherType::All) {
+        // the catch-all rule always exists, it can only be updated through its own endpoint
+        let rule = cloudflare_api::UpdateCatchAllRuleRequest {
+            actions: vec![action],
+            matchers: vec![matcher],
+            enabled: Some(!disabled),
+            name,
+            priority: None,
+        };
+
+        return update_catch_all_rule(global, &client, &zone, &rule).await;
+    }
+
+    if let EmailRoutingRuleMatcherType::Literal { value, .. } = &matcher.matcher_type
+        && let Some(existing) = find_literal_rule(&existing_rules, value)
+    {
+        if !force {
+            bail!("A rule for {value} already exists: {existing}\nUse --force to replace it.");
+        }
+
+        let rule = cloudflare_api::UpdateRoutingRuleRequest {
+            actions: vec![action],
+            matchers: vec![matcher],
+            enabled: Some(!disabled),
+            name: name.or_else(|| existing.name.clone()),
+            priority: priority.or(existing.priority),
+        };
+
+        let rule = client
+            .update_routing_rule(&zone.id, &existing.id, &rule)
+            .await
+            .permission(RULES_PERMISSION)
+            .context("Failed to replace rule")?;
+
+        eprintln!("Rule replaced.");
+        remember_rule(&zone, &rule, expires_at, tags, note, site)?;
+        output::print_item(global.output, &rule)?;
+
+        return Ok(());
+    }
+
     let rule = cloudflare_api::CreateRoutingRuleRequest {
         actions: vec![action],
         matchers: vec![matcher],
-        enabled: None,
+        enabled: disabled.then_some(false),
         name,
         priority,
     };
 
-    let response = client.create_routing_rule(&zone.id, &rule).await?;
+    let rule = client
+        .create_routing_rule(&zone.id, &rule)
+        .await
+        .permission(RULES_PERMISSION)
+        .context("Failed to create rule")?;
 
-    if let Some(rule) = response.result {
-        println!("Rule created: {rule}");
-    } else {
-        bail!("Failed to create rule: {response:?}")
+    eprintln!("Rule created.");
+    remember_rule(&zone, &rule, expires_at, tags, note, site)?;
+    output::print_item(global.output, &rule)?;
+
+    Ok(())
+}
+
+/// Saves what Cloudflare has no place for about a created rule. The metadata of a replaced rule
+/// is kept, unless given again.
+fn remember_rule(
+    zone: &cloudflare_api::Zone,
+    rule: &cloudflare_api::EmailRoutingRule,
+    expires_at: Option<DateTime<Utc>>,
+    tags: Vec<String>,
+    note: Option<String>,
+    site: Option<String>,
+) -> anyhow::Result<()> {
+    let mut state = state::load_state()?;
+    let previous = state.rules.remove(&rule.id);
+    let previous = previous.as_ref();
+
+    let rule_state = state::RuleState {
+        zone_id: zone.id.clone(),
+        email: output::join_display(&rule.matchers),
+        expires_at: expires_at.or(previous.and_then(|previous| previous.expires_at)),
+        tags: match tags.is_empty() {
+            true => previous
+                .map(|previous| previous.tags.clone())
+                .unwrap_or_default(),
+            false => tags,
+        },
+        note: note.or_else(|| previous.and_then(|previous| previous.note.clone())),
+        site: site.or_else(|| previous.and_then(|previous| previous.site.clone())),
+        created_at: previous
+            .and_then(|previous| previous.created_at)
+            .or(Some(Utc::now())),
+    };
+    state.rules.insert(rule.id.clone(), rule_state);
+    state::save_state(&state).context("The rule was saved, but not its metadata")?;
+
+    if let Some(expires_at) = expires_at {
+        eprintln!(
+            "The rule expires at {}, run sweep to remove it then.",
+            expires_at.to_rfc3339_opts(SecondsFormat::Secs, true)
+        );
     }
 
     Ok(())
 }
 
-pub async fn handle_list_addresses() -> anyhow::Result<()> {
-    let client = create_cf_client_from_config().await?;
-    let zone = select_first_zone(&client).await?;
+/// Deletes, or disables, the rules created with a TTL that has passed.
+pub async fn handle_sweep(global: &GlobalArgs, disable: bool, dry_run: bool) -> anyhow::Result<()> {
+    let config = load_existing_config(global)?;
+    let client = create_cf_client(&config).await?;
+
+    let mut state = state::load_state()?;
+    let expired = state.expired(Utc::now());
 
-    let addresses = client.list_destination_addresses(&zone.account.id).await?;
+    if expired.is_empty() {
+        eprintln!("No expired rules.");
+        return output::print_list::<state::SweepResult>(global.output, &[]);
+    }
 
-    if let Some(addresses) = addresses.result {
-        if addresses.is_empty() {
-            println!("No addresses found.");
+    // the state has the rules of every profile, only the zones of this one can be swept
+    let zones = client.list_zones().await.permission(ZONE_PERMISSION)?;
+
+    let mut results = vec![];
+    for (rule_id, rule_state) in expired {
+        let (status, details) = if !zones.iter().any(|zone| zone.id == rule_state.zone_id) {
+            (
+                state::SweepStatus::Skipped,
+                format!(
+                    "Zone {} isn't accessible with these credentials",
+                    rule_state.zone_id
+                ),
+            )
+        } else if dry_run {
+            let action = if disable { "disabled" } else { "deleted" };
+            (state::SweepStatus::Expired, format!("Would be {action}"))
         } else {
-            println!("Addresses:");
-            for address in addresses {
-                println!("  - {}", address);
+            let result = if disable {
+                disable_expired_rule(&client, &rule_state.zone_id, &rule_id).await
+            } else {
+                client
+                    .delete_routing_rule(&rule_state.zone_id, &rule_id)
+                    .await
+                    .map(|_| state::SweepStatus::Deleted)
+            };
+
+            match result {
+                Ok(state::SweepStatus::Skipped) => {
+                    (state::SweepStatus::Skipped, "Already disabled".to_string())
+                }
+                Ok(status) => (status, format!("ID: {rule_id}")),
+                Err(err) if err.status() == Some(reqwest::StatusCode::NOT_FOUND) => (
+                    state::SweepStatus::Missing,
+                    "Already deleted, forgotten".to_string(),
+                ),
+                Err(err) => (state::SweepStatus::Failed, describe_error(err)),
             }
+        };
+
+        // disabled rules are kept, so they can be deleted by a later sweep
+        if matches!(
+            status,
+            state::SweepStatus::Deleted | state::SweepStatus::Missing
+        ) {
+            state.rules.remove(&rule_id);
         }
-    } else {
-        bail!("Failed to list addresses: {addresses:?}")
+
+        results.push(state::SweepResult {
+            email: rule_state.email,
+            expired_at: rule_state.expires_at,
+            status,
+            details,
+        });
+    }
+
+    if !dry_run {
+        state::save_state(&state)?;
+    }
+
+    output::print_list(global.output, &results)?;
+
+    let failed = results
+        .iter()
+        .filter(|result| matches!(result.status, state::SweepStatus::Failed))
+        .count();
+    if failed > 0 {
+        bail!("Failed to sweep {failed} rules");
     }
 
     Ok(())
 }
 
-pub async fn handle_delete_rule(rule_identifier: String) -> anyhow::Result<()> {
-    let client = create_cf_client_from_config().await?;
-    let zone = select_first_zone(&client).await?;
+async fn disable_expired_rule(
+    client: &cloudflare_api::Client,
+    zone_id: &str,
+    rule_id: &str,
+) -> cloudflare_api::Result<state::SweepStatus> {
+    let rule = client.get_routing_rule(zone_id, rule_id).await?;
+    if !rule.enabled {
+        return Ok(state::SweepStatus::Skipped);
+    }
 
-    let response = client.list_email_routing_rules(&zone.id).await?;
+    let request = cloudflare_api::UpdateRoutingRuleRequest {
+        enabled: Some(false),
+        ..rule.into()
+    };
+    client
+        .update_routing_rule(zone_id, rule_id, &request)
+        .await?;
 
-    let rule_identifier = match response.result {
-        None => {
-            println!(
-                "Fetching rule identifier failed. Assuming user provided an existing rule ID."
+    Ok(state::SweepStatus::Disabled)
+}
+
+fn find_literal_rule<'a>(
+    rules: &'a [cloudflare_api::EmailRoutingRule],
+    email: &str,
+) -> Option<&'a cloudflare_api::EmailRoutingRule> {
+    rules.iter().find(|rule| {
+        rule.literal_matchers()
+            .any(|existing| existing.eq_ignore_ascii_case(email))
+    })
+}
+
+pub async fn handle_show_catch_all(global: &GlobalArgs) -> anyhow::Result<()> {
+    let (client, zone) = connect(global).await?;
+
+    let rule = client
+        .get_catch_all_rule(&zone.id)
+        .await
+        .permission(RULES_PERMISSION)
+        .context("Failed to get catch-all rule")?;
+
+    output::print_item(global.output, &rule)?;
+
+    Ok(())
+}
+
+/// Updates the catch-all rule, keeping its current actions when `actions` is `None`.
+pub async fn handle_set_catch_all(
+    global: &GlobalArgs,
+    actions: Option<Vec<cloudflare_api::EmailRoutingRuleAction>>,
+    enabled: bool,
+) -> anyhow::Result<()> {
+    let (client, zone) = connect(global).await?;
+
+    let current = client
+        .get_catch_all_rule(&zone.id)
+        .await
+        .permission(RULES_PERMISSION)
+        .context("Failed to get catch-all rule")?;
+
+    let rule = cloudflare_api::UpdateCatchAllRuleRequest {
+        actions: actions.unwrap_or(current.actions),
+        matchers: vec![EmailRoutingRuleMatcher {
+            matcher_type: EmailRoutingRuleMatcherType::All,
+        }],
+        enabled: Some(enabled),
+        name: current.name,
+        priority: None,
+    };
+
+    update_catch_all_rule(global, &client, &zone, &rule).await
+}
+
+async fn update_catch_all_rule(
+    global: &GlobalArgs,
+    client: &cloudflare_api::Client,
+    zone: &cloudflare_api::Zone,
+    rule: &cloudflare_api::UpdateCatchAllRuleRequest,
+) -> anyhow::Result<()> {
+    let rule = client
+        .update_catch_all_rule(&zone.id, rule)
+        .await
+        .permission(RULES_PERMISSION)
+        .context("Failed to update catch-all rule")?;
+
+    eprintln!("Catch-all rule updated.");
+    output::print_item(global.output, &rule)?;
+
+    Ok(())
+}
+
+fn print_routing_readiness(settings: &cloudflare_api::EmailRoutingSettings) {
+    match &settings.status {
+        Some(cloudflare_api::EmailRoutingStatus::Ready) if settings.enabled => {
+            eprintln!("Email routing is enabled and ready for {}.", settings.name);
+        }
+        _ if !settings.enabled => {
+            eprintln!(
+                "Email routing is disabled for {}. Enable it with `routing enable`.",
+                settings.name
             );
-            rule_identifier
         }
-        Some(rules) => {
-            fn string_kinda_matches(input: &str, other: &str) -> bool {
-                other.to_lowercase().contains(&input.to_lowercase())
-            }
+        status => {
+            let status = status
+                .as_ref()
+                .map_or("unknown".to_string(), ToString::to_string);
+            eprintln!(
+                "Email routing for {} is not ready (status: {status}). \
+                 Check the required DNS records with `routing dns`.",
+                settings.name
+            );
+        }
+    }
+}
 
-            let matched_rules = rules
-                .iter()
-                .filter(|rule| {
-                    string_kinda_matches(&rule_identifier, &rule.id)
-                        || rule.matchers.iter().any(|matcher| match matcher {
-                            EmailRoutingRuleMatcher {
-                                matcher_type: EmailRoutingRuleMatcherType::All,
-                            } => false, // catch-all rules can't match
-                            EmailRoutingRuleMatcher {
-                                matcher_type: EmailRoutingRuleMatcherType::Literal { value },
-                            } => string_kinda_matches(&rule_identifier, value),
-                        })
-                })
-                .collect::<Vec<_>>();
-
-            let rule_identifier = match matched_rules.as_slice() {
-                [] => {
-                    println!("No rules found with identifier {rule_identifier}.");
-                    println!("Available rules:");
-                    for rule in &rules {
-                        println!("  - {rule}");
-                    }
-                    return Ok(());
-                }
-                [rule] => {
-                    println!("Found rule: {rule}");
-                    rule.id.clone()
-                }
-                rules => {
-                    println!("Multiple rules found with identifier {rule_identifier}:");
-                    for rule in rules {
-                        println!("  - {rule}");
+pub async fn handle_routing_status(global: &GlobalArgs) -> anyhow::Result<()> {
+    let (client, zone) = connect(global).await?;
+
+    let settings = client
+        .get_email_routing_settings(&zone.id)
+        .await
+        .permission(SETTINGS_PERMISSION)
+        .context("Failed to get email routing settings")?;
+
+    print_routing_readiness(&settings);
+    output::print_item(global.output, &settings)?;
+
+    Ok(())
+}
+
+pub async fn handle_set_routing_enabled(global: &GlobalArgs, enabled: bool) -> anyhow::Result<()> {
+    let (client, zone) = connect(global).await?;
+
+    let settings = if enabled {
+        client.enable_email_routing(&zone.id).await
+    } else {
+        client.disable_email_routing(&zone.id).await
+    }
+    .permission(SETTINGS_PERMISSION)
+    .context("Failed to update email routing")?;
+
+    print_routing_readiness(&settings);
+    output::print_item(global.output, &settings)?;
+
+    Ok(())
+}
+
+pub async fn handle_routing_dns(global: &GlobalArgs) -> anyhow::Result<()> {
+    let (client, zone) = connect(global).await?;
+
+    let settings = client
+        .get_email_routing_settings(&zone.id)
+        .await
+        .permission(SETTINGS_PERMISSION)
+        .context("Failed to get email routing settings")?;
+
+    let records = client
+        .get_email_routing_dns_records(&zone.id)
+        .await
+        .permission(SETTINGS_PERMISSION)
+        .context("Failed to get email routing dns records")?;
+
+    print_routing_readiness(&settings);
+    eprintln!("Required DNS records:");
+    output::print_list(global.output, &records)?;
+
+    Ok(())
+}
+
+pub async fn handle_list_addresses(global: &GlobalArgs) -> anyhow::Result<()> {
+    let (client, zone) = connect(global).await?;
+
+    let addresses = client
+        .list_destination_addresses(&zone.account.id)
+        .await
+        .permission(ADDRESSES_PERMISSION)?;
+
+    if addresses.is_empty() {
+        eprintln!("No addresses found.");
+    }
+
+    output::print_list(global.output, &addresses)?;
+
+    Ok(())
+}
+
+/// Finds the single destination address whose ID or email contains the given identifier
+/// (case-insensitive).
+async fn resolve_address(
+    client: &cloudflare_api::Client,
+    zone: &cloudflare_api::Zone,
+    address_identifier: &str,
+) -> anyhow::Result<cloudflare_api::Address> {
+    let addresses = client
+        .list_destination_addresses(&zone.account.id)
+        .await
+        .permission(ADDRESSES_PERMISSION)?;
+
+    resolve("address", addresses, address_identifier, |address| {
+        [&address.id, &address.email]
+            .into_iter()
+            .flatten()
+            .any(|value| string_kinda_matches(address_identifier, value))
+    })
+}
+
+pub async fn handle_add_address(global: &GlobalArgs, email: String) -> anyhow::Result<()> {
+    let (client, zone) = connect(global).await?;
+
+    let address = client
+        .create_destination_address(&zone.account.id, &email)
+        .await
+        .permission(ADDRESSES_PERMISSION)
+        .context("Failed to add address")?;
+
+    eprintln!("Address added. Cloudflare sent an email to {email} to verify it.");
+    output::print_item(global.output, &address)?;
+
+    Ok(())
+}
+
+pub async fn handle_show_address(
+    global: &GlobalArgs,
+    address_identifier: String,
+) -> anyhow::Result<()> {
+    let (client, zone) = connect(global).await?;
+
+    let address = resolve_address(&client, &zone, &address_identifier).await?;
+
+    let address_id = address.id.context("Address has no id")?;
+    let address = client
+        .get_destination_address(&zone.account.id, &address_id)
+        .await
+        .permission(ADDRESSES_PERMISSION)
+        .context("Failed to get address")?;
+
+    output::print_item(global.output, &address)?;
+
+    Ok(())
+}
+
+pub async fn handle_remove_address(
+    global: &GlobalArgs,
+    address_identifier: String,
+) -> anyhow::Result<()> {
+    let (client, zone) = connect(global).await?;
+
+    let address = resolve_address(&client, &zone, &address_identifier).await?;
+
+    // only to warn about the rules that forward to it, the address can be removed without them
+    let rules = client
+        .list_email_routing_rules(&zone.id)
+        .await
+        .permission(RULES_PERMISSION);
+    let rules = match rules {
+        Ok(rules) => rules,
+        Err(err) => {
+            eprintln!("Warning: failed to check the rules forwarding to the address: {err:#}");
+            vec![]
+        }
+    };
+
+    if let Some(email) = &address.email {
+        let forwarding_rules = rules
+            .iter()
+            .filter(|rule| {
+                rule.actions.iter().any(|action| match &action.action_type {
+                    cloudflare_api::EmailRoutingRuleActionType::Forward { value } => {
+                        value.iter().any(|value| value.eq_ignore_ascii_case(email))
                     }
-                    println!("Please specify a unique identifier.");
-                    return Ok(());
+                    _ => false,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        if !forwarding_rules.is_empty() {
+            eprintln!("Warning: these rules forward to {email} and will stop working:");
+            for rule in &forwarding_rules {
+                eprintln!("  - {rule}");
+            }
+        }
+    }
+
+    let address_id = address.id.as_deref().context("Address has no id")?;
+    let deleted = client
+        .delete_destination_address(&zone.account.id, address_id)
+        .await
+        .permission(ADDRESSES_PERMISSION)
+        .context("Failed to remove address")?;
+
+    eprintln!("Address removed successfully.");
+    output::print_item(global.output, deleted.as_ref().unwrap_or(&address))?;
+
+    Ok(())
+}
+
+fn string_kinda_matches(input: &str, other: &str) -> bool {
+    other.to_lowercase().contains(&input.to_lowercase())
+}
+
+/// Finds the single rule whose ID or matcher contains the given identifier (case-insensitive).
+async fn resolve_rule(
+    client: &cloudflare_api::Client,
+    zone: &cloudflare_api::Zone,
+    rule_identifier: &str,
+) -> anyhow::Result<cloudflare_api::EmailRoutingRule> {
+    let rules = client
+        .list_email_routing_rules(&zone.id)
+        .await
+        .permission(RULES_PERMISSION)?;
+
+    resolve("rule", rules, rule_identifier, |rule| {
+        string_kinda_matches(rule_identifier, &rule.id)
+            || rule.matchers.iter().any(|matcher| match matcher {
+                EmailRoutingRuleMatcher {
+                    matcher_type: EmailRoutingRuleMatcherType::All,
+                } => false, // catch-all rules can't match
+                EmailRoutingRuleMatcher {
+                    matcher_type: EmailRoutingRuleMatcherType::Literal { value, .. },
+                } => string_kinda_matches(rule_identifier, value),
+            })
+    })
+}
+
+/// Picks the single item that matches an identifier given by the user.
+///
+/// Fails with the candidates in the message when no item or multiple items match.
+fn resolve<T: std::fmt::Display>(
+    kind: &str,
+    mut items: Vec<T>,
+    identifier: &str,
+    matches: impl Fn(&T) -> bool,
+) -> anyhow::Result<T> {
+    let matched = items
+        .iter()
+        .enumerate()
+        .filter(|(_, item)| matches(item))
+        .map(|(index, _)| index)
+        .collect::<Vec<_>>();
+
+    match matched.as_slice() {
+        [] => bail!(
+            "No {kind} found with identifier {identifier}. Available:\n{}",
+            format_list(&items)
+        ),
+        [index] => {
+            let item = items.swap_remove(*index);
+            eprintln!("Found {kind}: {item}");
+            Ok(item)
+        }
+        matched => bail!(
+            "More than one {kind} found with identifier {identifier}, \
+             please specify a unique one:\n{}",
+            format_list(matched.iter().map(|index| &items[*index]))
+        ),
+    }
+}
+
+pub async fn handle_delete_rule(
+    global: &GlobalArgs,
+    rule_identifier: String,
+) -> anyhow::Result<()> {
+    let (client, zone) = connect(global).await?;
+
+    let rule = resolve_rule(&client, &zone, &rule_identifier).await?;
+
+    let deleted = client
+        .delete_routing_rule(&zone.id, &rule.id)
+        .await
+        .permission(RULES_PERMISSION)
+        .context("Failed to delete rule")?;
+
+    if let Err(err) = state::forget_rule(&rule.id) {
+        eprintln!("Warning: failed to forget the metadata of the rule: {err:#}");
+    }
+
+    eprintln!("Rule deleted successfully.");
+    // the api answers with the deleted rule, fall back to the one we found
+    output::print_item(global.output, deleted.as_ref().unwrap_or(&rule))?;
+
+    Ok(())
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum DeleteStatus {
+    Deleted,
+    /// The rule was deleted by something else in the meantime.
+    Missing,
+    Failed,
+}
+
+/// Outcome of deleting one of the rules matched by `delete --all-matching`.
+#[derive(Serialize, Debug)]
+pub struct DeleteResult {
+    pub id: String,
+    pub email: String,
+    pub status: DeleteStatus,
+    pub details: String,
+}
+
+/// How many rules `delete --all-matching` deletes at the same time.
+const MAX_CONCURRENT_DELETES: usize = 4;
+
+pub async fn handle_delete_matching_rules(
+    global: &GlobalArgs,
+    pattern: String,
+    regex: bool,
+    yes: bool,
+) -> anyhow::Result<()> {
+    // unlike in list, the whole matcher must match, as it deletes everything that does
+    if pattern.is_empty() {
+        bail!("The pattern can't be empty");
+    }
+    let matcher = if regex {
+        filter::Pattern::new(&pattern, true)?
+    } else {
+        filter::Pattern::glob(&pattern)?
+    };
+    let rule_filter = filter::RuleFilter {
+        matcher: Some(matcher),
+        ..filter::RuleFilter::default()
+    };
+
+    let (client, zone) = connect(global).await?;
+
+    let mut rules = client
+        .list_email_routing_rules(&zone.id)
+        .await
+        .permission(RULES_PERMISSION)?;
+    rules.retain(|rule| rule_filter.matches(rule));
+
+    if rules.is_empty() {
+        eprintln!("No rules found matching {pattern}.");
+        return output::print_list::<DeleteResult>(global.output, &[]);
+    }
+
+    eprintln!("Found {} rules matching {pattern}:", rules.len());
+    for rule in &rules {
+        eprintln!("  - {rule}");
+    }
+
+    if !yes && !confirm(&format!("Delete these {} rules?", rules.len()))? {
+        eprintln!("No rules were deleted.");
+        return Ok(());
+    }
+
+    let results = futures::stream::iter(&rules)
+        .map(|rule| async {
+            let (status, details) = match client.delete_routing_rule(&zone.id, &rule.id).await {
+                Ok(_) => (DeleteStatus::Deleted, String::new()),
+                Err(err) if err.status() == Some(reqwest::StatusCode::NOT_FOUND) => {
+                    (DeleteStatus::Missing, "Already deleted".to_string())
                 }
+                Err(err) => (DeleteStatus::Failed, describe_error(err)),
             };
 
-            rule_identifier
+            DeleteResult {
+                id: rule.id.clone(),
+                email: output::join_display(&rule.matchers),
+                status,
+                details,
+            }
+        })
+        // keeps the order of the rules, unlike buffer_unordered
+        .buffered(MAX_CONCURRENT_DELETES)
+        .collect::<Vec<_>>()
+        .await;
+
+    let mut state = state::load_state()?;
+    for result in &results {
+        if !matches!(result.status, DeleteStatus::Failed) {
+            state.rules.remove(&result.id);
         }
+    }
+    if let Err(err) = state::save_state(&state) {
+        eprintln!("Warning: failed to forget the metadata of the rules: {err:#}");
+    }
+
+    output::print_list(global.output, &results)?;
+
+    let failed = results
+        .iter()
+        .filter(|result| matches!(result.status, DeleteStatus::Failed))
+        .count();
+    if failed > 0 {
+        bail!("Failed to delete {failed} of {} rules", results.len());
+    }
+
+    Ok(())
+}
+
+/// Asks a yes or no question on the terminal, refusing to guess when there is none.
+fn confirm(question: &str) -> anyhow::Result<bool> {
+    if !std::io::stdin().is_terminal() {
+        bail!("Not asking for confirmation without a terminal, pass --yes to skip it");
+    }
+
+    eprint!("{question} [y/N] ");
+    let mut answer = String::new();
+    std::io::stdin()
+        .read_line(&mut answer)
+        .context("Failed to read the answer")?;
+
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Everything known about a rule, for `show`.
+#[derive(Serialize)]
+struct RuleDetails<'a> {
+    #[serde(flatten)]
+    rule: &'a cloudflare_api::EmailRoutingRule,
+    destinations: Vec<DestinationDetails>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadata: Option<&'a state::RuleState>,
+}
+
+#[derive(Serialize)]
+struct DestinationDetails {
+    email: String,
+    /// Whether it's a destination address of the account at all.
+    exists: bool,
+    verified: Option<String>,
+}
+
+pub async fn handle_show_rule(global: &GlobalArgs, rule_identifier: String) -> anyhow::Result<()> {
+    let (client, zone) = connect(global).await?;
+
+    let rule = resolve_rule(&client, &zone, &rule_identifier).await?;
+
+    // the list may not include every detail of the rules
+    let rule = client
+        .get_routing_rule(&zone.id, &rule.id)
+        .await
+        .permission(RULES_PERMISSION)
+        .context("Failed to get rule")?;
+    let addresses = client
+        .list_destination_addresses(&zone.account.id)
+        .await
+        .permission(ADDRESSES_PERMISSION)?;
+    let state = state::load_state()?;
+
+    let destinations = rule
+        .actions
+        .iter()
+        .flat_map(|action| match &action.action_type {
+            cloudflare_api::EmailRoutingRuleActionType::Forward { value } => value.as_slice(),
+            _ => &[],
+        })
+        .map(|email| {
+            let address = addresses.iter().find(|address| {
+                address
+                    .email
+                    .as_ref()
+                    .is_some_and(|existing| existing.eq_ignore_ascii_case(email))
+            });
+            DestinationDetails {
+                email: email.clone(),
+                exists: address.is_some(),
+                verified: address.and_then(|address| address.verified.clone()),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let details = RuleDetails {
+        rule: &rule,
+        destinations,
+        metadata: state.rules.get(&rule.id),
     };
 
-    let response = client
-        .delete_routing_rule(&zone.id, &rule_identifier)
-        .await?;
+    let mut fields = vec![
+        ("ID", rule.id.clone()),
+        ("TAG", rule.tag.clone().unwrap_or_default()),
+        ("NAME", rule.name.clone().unwrap_or_default()),
+        ("ENABLED", rule.enabled.to_string()),
+        (
+            "PRIORITY",
+            rule.priority
+                .map(|priority| priority.to_string())
+                .unwrap_or_default(),
+        ),
+    ];
+    for matcher in &rule.matchers {
+        let value = match matcher.matcher_type.field() {
+            Some(field) => format!("{matcher} (field: {field})"),
+            None => matcher.to_string(),
+        };
+        fields.push(("MATCHER", value));
+    }
+    for action in &rule.actions {
+        fields.push(("ACTION", action.to_string()));
+    }
+    for destination in &details.destinations {
+        let status = match (&destination.verified, destination.exists) {
+            (Some(verified), _) => format!("verified on {verified}"),
+            (None, true) => "not verified".to_string(),
+            (None, false) => "not a destination address".to_string(),
+        };
+        fields.push(("DESTINATION", format!("{} ({status})", destination.email)));
+    }
+    if let Some(metadata) = details.metadata {
+        let timestamp =
+            |timestamp: DateTime<Utc>| timestamp.to_rfc3339_opts(SecondsFormat::Secs, true);
+        if let Some(site) = &metadata.site {
+            fields.push(("SITE", site.clone()));
+        }
+        if !metadata.tags.is_empty() {
+            fields.push(("TAGS", metadata.tags.join(", ")));
+        }
+        if let Some(note) = &metadata.note {
+            fields.push(("NOTE", note.clone()));
+        }
+        if let Some(created_at) = metadata.created_at {
+            fields.push(("CREATED", timestamp(created_at)));
+        }
+        if let Some(expires_at) = metadata.expires_at {
+            fields.push(("EXPIRES", timestamp(expires_at)));
+        }
+    }
 
-    if response.success {
-        println!("Rule deleted successfully.");
-    } else {
-        bail!("Failed to delete rule: {response:?}");
+    output::print_details(global.output, &details, &fields)?;
+
+    Ok(())
+}
+
+pub async fn handle_update_rule(
+    global: &GlobalArgs,
+    rule_identifier: String,
+    matcher: Option<EmailRoutingRuleMatcher>,
+    actions: Vec<cloudflare_api::EmailRoutingRuleAction>,
+    name: Option<String>,
+    priority: Option<usize>,
+    enabled: Option<bool>,
+) -> anyhow::Result<()> {
+    if matcher.is_none()
+        && actions.is_empty()
+        && name.is_none()
+        && priority.is_none()
+        && enabled.is_none()
+    {
+        bail!("Nothing to update. Specify at least one of the fields to change.");
     }
+
+    let (client, zone) = connect(global).await?;
+
+    let rule = resolve_rule(&client, &zone, &rule_identifier).await?;
+
+    // fetch the rule again so the update is based on its latest state
+    let rule = client
+        .get_routing_rule(&zone.id, &rule.id)
+        .await
+        .permission(RULES_PERMISSION)
+        .context("Failed to get rule")?;
+
+    let matchers = match matcher {
+        Some(matcher) => vec![complete_matcher(&client, &zone, matcher).await?],
+        None => rule.matchers,
+    };
+
+    let request = cloudflare_api::UpdateRoutingRuleRequest {
+        actions: if actions.is_empty() {
+            rule.actions
+        } else {
+            actions
+        },
+        matchers,
+        enabled: Some(enabled.unwrap_or(rule.enabled)),
+        name: name.or(rule.name),
+        priority: priority.or(rule.priority),
+    };
+
+    let rule = client
+        .update_routing_rule(&zone.id, &rule.id, &request)
+        .await
+        .permission(RULES_PERMISSION)
+        .context("Failed to update rule")?;
+
+    eprintln!("Rule updated.");
+    output::print_item(global.output, &rule)?;
+
+    Ok(())
+}
+
+pub async fn handle_set_rule_enabled(
+    global: &GlobalArgs,
+    rule_identifier: String,
+    enabled: bool,
+) -> anyhow::Result<()> {
+    handle_update_rule(
+        global,
+        rule_identifier,
+        None,
+        vec![],
+        None,
+        None,
+        Some(enabled),
+    )
+    .await
+}
+
+async fn plan_manifest(
+    global: &GlobalArgs,
+    manifest_path: &Path,
+    prune: bool,
+) -> anyhow::Result<(
+    cloudflare_api::Client,
+    Vec<(cloudflare_api::Zone, manifest::ZonePlan)>,
+)> {
+    let manifest = manifest::load_manifest(manifest_path)?;
+    let config = load_existing_config(global)?;
+    let client = create_cf_client(&config).await?;
+
+    let mut plans = vec![];
+    for zone_manifest in &manifest.zones {
+        let zone_identifier = zone_manifest
+            .zone
+            .as_deref()
+            .or(global.zone.as_deref())
+            .or(config.default_zone.as_deref());
+        let zone = select_zone(&client, zone_identifier).await?;
+
+        let rules = client
+            .list_email_routing_rules(&zone.id)
+            .await
+            .permission(RULES_PERMISSION)?;
+        let addresses = client
+            .list_destination_addresses(&zone.account.id)
+            .await
+            .permission(ADDRESSES_PERMISSION)?;
+
+        let plan = manifest::plan_zone(zone_manifest, &zone.name, rules, &addresses, prune)
+            .with_context(|| format!("Failed to plan zone {zone}"))?;
+        plans.push((zone, plan));
+    }
+
+    Ok((client, plans))
+}
+
+fn print_plan(
+    global: &GlobalArgs,
+    plans: &[(cloudflare_api::Zone, manifest::ZonePlan)],
+) -> anyhow::Result<()> {
+    for (zone, plan) in plans {
+        if !plan.unmanaged.is_empty() {
+            eprintln!(
+                "Keeping {} rules of {} that aren't in the manifest (use --prune to delete them):",
+                plan.unmanaged.len(),
+                zone.name
+            );
+            for rule in &plan.unmanaged {
+                eprintln!("  - {rule}");
+            }
+        }
+    }
+
+    let operations = plans
+        .iter()
+        .flat_map(|(zone, plan)| {
+            plan.operations
+                .iter()
+                .map(|operation| manifest::PlannedOperation {
+                    zone: &zone.name,
+                    operation,
+                })
+        })
+        .collect::<Vec<_>>();
+
+    if operations.is_empty() {
+        eprintln!("No changes. The zones match the manifest.");
+    }
+
+    output::print_list(global.output, &operations)
+}
+
+pub async fn handle_plan(
+    global: &GlobalArgs,
+    manifest_path: PathBuf,
+    prune: bool,
+) -> anyhow::Result<()> {
+    let (_, plans) = plan_manifest(global, &manifest_path, prune).await?;
+    print_plan(global, &plans)
+}
+
+pub async fn handle_apply(
+    global: &GlobalArgs,
+    manifest_path: PathBuf,
+    prune: bool,
+) -> anyhow::Result<()> {
+    let (client, plans) = plan_manifest(global, &manifest_path, prune).await?;
+    print_plan(global, &plans)?;
+
+    let mut applied = 0;
+    for (zone, plan) in plans {
+        for operation in plan.operations {
+            apply_operation(&client, &zone, operation).await?;
+            applied += 1;
+        }
+    }
+
+    eprintln!("Applied {applied} operations.");
+
+    Ok(())
+}
+
+async fn apply_operation(
+    client: &cloudflare_api::Client,
+    zone: &cloudflare_api::Zone,
+    operation: manifest::Operation,
+) -> anyhow::Result<()> {
+    match operation {
+        manifest::Operation::CreateDestination { email } => {
+            client
+                .create_destination_address(&zone.account.id, &email)
+                .await
+                .permission(ADDRESSES_PERMISSION)
+                .with_context(|| format!("Failed to create destination address {email}"))?;
+            eprintln!("Created destination address {email}. It must be verified before use.");
+        }
+        manifest::Operation::CreateRule { rule } => {
+            let rule = client
+                .create_routing_rule(&zone.id, &rule)
+                .await
+                .permission(RULES_PERMISSION)
+                .context("Failed to create rule")?;
+            eprintln!("Created rule: {rule}");
+        }
+        manifest::Operation::UpdateRule { id, rule, .. } => {
+            let rule = client
+                .update_routing_rule(&zone.id, &id, &rule)
+                .await
+                .permission(RULES_PERMISSION)
+                .context("Failed to update rule")?;
+            eprintln!("Updated rule: {rule}");
+        }
+        manifest::Operation::DeleteRule { rule } => {
+            client
+                .delete_routing_rule(&zone.id, &rule.id)
+                .await
+                .permission(RULES_PERMISSION)
+                .with_context(|| format!("Failed to delete rule {rule}"))?;
+            eprintln!("Deleted rule: {rule}");
+        }
+    }
+
     Ok(())
 }
 
-pub async fn handle_list_zones() -> anyhow::Result<()> {
-    let client = create_cf_client_from_config().await?;
+pub async fn handle_export(
+    global: &GlobalArgs,
+    path: PathBuf,
+    format: Option<backup::BackupFormat>,
+) -> anyhow::Result<()> {
+    let (client, zone) = connect(global).await?;
+
+    let rules = client
+        .list_email_routing_rules(&zone.id)
+        .await
+        .permission(RULES_PERMISSION)?;
+    let addresses = client
+        .list_destination_addresses(&zone.account.id)
+        .await
+        .permission(ADDRESSES_PERMISSION)?;
+
+    let backup = backup::Backup {
+        version: backup::BACKUP_VERSION,
+        zone: zone.name,
+        rules,
+        addresses,
+    };
+
+    let format = format.unwrap_or_else(|| backup::BackupFormat::from_path(&path));
+    backup::write_backup(&path, &backup, format)?;
+
+    eprintln!(
+        "Exported {} rules and {} addresses to {}",
+        backup.rules.len(),
+        backup.addresses.len(),
+        path.display()
+    );
+
+    Ok(())
+}
+
+/// Moves the addresses of `from_domain` matched by the rule to `to_domain`.
+fn move_rule_domain(
+    rule: &mut cloudflare_api::EmailRoutingRule,
+    from_domain: &str,
+    to_domain: &str,
+) {
+    for matcher in &mut rule.matchers {
+        if let EmailRoutingRuleMatcherType::Literal { value, .. } = &mut matcher.matcher_type
+            && let Some((username, domain)) = value.rsplit_once('@')
+            && domain.eq_ignore_ascii_case(from_domain)
+        {
+            *value = format!("{username}@{to_domain}");
+        }
+    }
+}
+
+pub async fn handle_import(
+    global: &GlobalArgs,
+    path: PathBuf,
+    format: Option<backup::BackupFormat>,
+) -> anyhow::Result<()> {
+    let format = format.unwrap_or_else(|| backup::BackupFormat::from_path(&path));
+    let backup = backup::read_backup(&path, format)?;
+    let (client, zone) = connect(global).await?;
+
+    let existing_rules = client
+        .list_email_routing_rules(&zone.id)
+        .await
+        .permission(RULES_PERMISSION)?;
+    let existing_addresses = client
+        .list_destination_addresses(&zone.account.id)
+        .await
+        .permission(ADDRESSES_PERMISSION)?;
+
+    let move_domain = !backup.zone.eq_ignore_ascii_case(&zone.name);
+    if move_domain {
+        eprintln!(
+            "Importing rules exported from {} into {}, their addresses will use the domain {}.",
+            backup.zone, zone.name, zone.name
+        );
+    }
+
+    let mut results = vec![];
+
+    for address in backup.addresses {
+        let Some(email) = address.email else {
+            continue;
+        };
 
-    let response = client.list_zones().await?;
+        let exists = existing_addresses.iter().any(|existing| {
+            existing
+                .email
+                .as_ref()
+                .is_some_and(|existing| existing.eq_ignore_ascii_case(&email))
+        });
 
-    if let Some(zones) = response.result {
-        if zones.is_empty() {
-            println!("No zones found.");
+        let (status, details) = if exists {
+            (backup::ImportStatus::Skipped, "Already exists".to_string())
         } else {
-            println!("Zones:");
-            for zone in zones {
-                println!("  - {zone}");
+            match client
+                .create_destination_address(&zone.account.id, &email)
+                .await
+            {
+                Ok(_) => (
+                    backup::ImportStatus::Created,
+                    "Must be verified before use".to_string(),
+                ),
+                Err(err) => (backup::ImportStatus::Failed, describe_error(err)),
             }
+        };
+
+        results.push(backup::ImportResult {
+            target: email,
+            status,
+            details,
+        });
+    }
+
+    for mut rule in backup.rules {
+        if rule.is_catch_all() {
+            results.push(backup::ImportResult {
+                target: output::join_display(&rule.matchers),
+                status: backup::ImportStatus::Skipped,
+                details: "Use the catch-all command to restore the catch-all rule".to_string(),
+            });
+            continue;
         }
-    } else {
-        bail!("Failed to list zones: {response:?}")
+
+        if move_domain {
+            move_rule_domain(&mut rule, &backup.zone, &zone.name);
+        }
+
+        let target = output::join_display(&rule.matchers);
+
+        let existing = existing_rules.iter().find(|existing| {
+            existing.literal_matchers().any(|existing| {
+                rule.literal_matchers()
+                    .any(|value| value.eq_ignore_ascii_case(existing))
+            })
+        });
+
+        let (status, details) = match existing {
+            Some(existing) if existing.actions == rule.actions => (
+                backup::ImportStatus::Skipped,
+                format!("Already exists (ID: {})", existing.id),
+            ),
+            Some(existing) => (
+                backup::ImportStatus::Conflict,
+                format!("Already exists with different actions: {existing}"),
+            ),
+            None => {
+                let request = cloudflare_api::CreateRoutingRuleRequest::from(rule);
+                match client.create_routing_rule(&zone.id, &request).await {
+                    Ok(created) => (backup::ImportStatus::Created, format!("ID: {}", created.id)),
+                    Err(err) => (backup::ImportStatus::Failed, describe_error(err)),
+                }
+            }
+        };
+
+        results.push(backup::ImportResult {
+            target,
+            status,
+            details,
+        });
+    }
+
+    output::print_list(global.output, &results)?;
+
+    let failed = results
+        .iter()
+        .filter(|result| matches!(result.status, backup::ImportStatus::Failed))
+        .count();
+    if failed > 0 {
+        bail!("Failed to import {failed} rules or addresses");
+    }
+
+    Ok(())
+}
+
+pub async fn handle_list_zones(
+    global: &GlobalArgs,
+    set_default: Option<String>,
+) -> anyhow::Result<()> {
+    let config = load_existing_config(global)?;
+    let client = create_cf_client(&config).await?;
+
+    if let Some(zone_identifier) = set_default {
+        let zone = select_zone(&client, Some(&zone_identifier)).await?;
+
+        let mut config_file = config::load_config()?;
+        let profile = config_file.profile_name(global.profile.as_deref());
+        config_file
+            .profiles
+            .entry(profile.to_string())
+            // only the default zone is saved when the credentials come from the environment
+            .or_insert_with(|| config::ClientConfig::new(config::StoredCredentials::Environment {}))
+            .default_zone = Some(zone.id);
+        let config_path = config::save_config(&config_file)?;
+        eprintln!("Default zone saved at {}", config_path.display());
+        return Ok(());
+    }
+
+    let zones = client.list_zones().await.permission(ZONE_PERMISSION)?;
+
+    if zones.is_empty() {
+        eprintln!("No zones found.");
     }
 
+    if let Some(default_zone) = &config.default_zone
+        && let Some(zone) = zones.iter().find(|zone| zone_matches(zone, default_zone))
+    {
+        eprintln!("Default zone: {zone}");
+    }
+
+    output::print_list(global.output, &zones)?;
+
     Ok(())
 }
 
@@ -364,7 +1780,7 @@ impl std::fmt::Display for EmailRoutingRuleMatcher {
             cloudflare_api::EmailRoutingRuleMatcherType::All => {
                 write!(f, "* (catch-all)")
             }
-            cloudflare_api::EmailRoutingRuleMatcherType::Literal { value } => {
+            cloudflare_api::EmailRoutingRuleMatcherType::Literal { value, .. } => {
                 write!(f, "{}", value)
             }
         }
@@ -377,20 +1793,20 @@ impl std::fmt::Display for cloudflare_api::EmailRoutingRule {
         write!(f, " -> ")?;
         write_vec(f, &self.actions)?;
         write!(f, " (ID: {}", self.id)?;
-        if let Some(name) = &self.name {
-            if !name.is_empty() {
-                write!(f, ", Name: {name}")?;
-            }
+        if let Some(name) = &self.name
+            && !name.is_empty()
+        {
+            write!(f, ", Name: {name}")?;
         }
 
         if !self.enabled {
             write!(f, ", Disabled")?;
         }
 
-        if let Some(priority) = self.priority {
-            if priority != 0 {
-                write!(f, ", Priority: {}", priority)?;
-            }
+        if let Some(priority) = self.priority
+            && priority != 0
+        {
+            write!(f, ", Priority: {}", priority)?;
         }
 
         write!(f, ")")?;
@@ -409,12 +1825,30 @@ impl std::fmt::Display for cloudflare_api::Address {
             write!(f, " (id = {})", id)?;
         }
 
+        if self.verified.is_none() {
+            write!(f, " [not verified]")?;
+        }
+
         Ok(())
     }
 }
 
+impl std::fmt::Display for cloudflare_api::EmailRoutingStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            cloudflare_api::EmailRoutingStatus::Ready => write!(f, "ready"),
+            cloudflare_api::EmailRoutingStatus::Unconfigured => write!(f, "unconfigured"),
+            cloudflare_api::EmailRoutingStatus::Misconfigured => write!(f, "misconfigured"),
+            cloudflare_api::EmailRoutingStatus::MisconfiguredOrLocked => {
+                write!(f, "misconfigured/locked")
+            }
+            cloudflare_api::EmailRoutingStatus::Unlocked => write!(f, "unlocked"),
+        }
+    }
+}
+
 impl std::fmt::Display for cloudflare_api::Zone {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{} (id = {})", self.account.name, self.id)
+        write!(f, "{} ({}, id = {})", self.name, self.account.name, self.id)
     }
 }
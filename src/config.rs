@@ -1,12 +1,262 @@
-use anyhow::Context;
+use crate::alias;
+use crate::cloudflare_api;
+use crate::secrets;
+use anyhow::{Context, bail};
 use directories::ProjectDirs;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::Duration;
 
-#[derive(Serialize, Deserialize, Default, Debug)]
+/// Overrides the `api_base_url` of the config, e.g. to point the tool to a mock server.
+pub const API_BASE_URL_ENV: &str = "CF_API_BASE_URL";
+/// Override the credentials of the config, so the tool can run without a setup, e.g. in CI.
+pub const API_TOKEN_ENV: &str = "CF_API_TOKEN";
+pub const EMAIL_ENV: &str = "CF_EMAIL";
+pub const API_KEY_ENV: &str = "CF_API_KEY";
+
+/// Profile used when none is given and the config doesn't name a default one. Configs written
+/// before profiles existed are migrated into it.
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// The config file, with the settings of each profile, e.g. one per Cloudflare account.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Config {
+    /// Profile used when `--profile` isn't given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, ClientConfig>,
+}
+
+impl Config {
+    /// Name of the given profile, or of the default one.
+    pub fn profile_name<'a>(&'a self, profile: Option<&'a str>) -> &'a str {
+        profile
+            .or(self.default_profile.as_deref())
+            .unwrap_or(DEFAULT_PROFILE)
+    }
+
+    pub fn summaries(&self) -> Vec<ProfileSummary> {
+        let default_profile = self.profile_name(None);
+
+        self.profiles
+            .iter()
+            .map(|(name, config)| ProfileSummary {
+                name: name.clone(),
+                default: name == default_profile,
+                credentials: config.credentials.kind(),
+                default_zone: config.default_zone.clone(),
+            })
+            .collect()
+    }
+}
+
+/// What `profile list` shows of a profile, without the credentials themselves.
+#[derive(Serialize, Debug)]
+pub struct ProfileSummary {
+    pub name: String,
+    pub default: bool,
+    pub credentials: &'static str,
+    pub default_zone: Option<String>,
+}
+
+/// Credentials as they're saved in the config file.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum StoredCredentials {
+    Encrypted {
+        encrypted_credentials: secrets::EncryptedCredentials,
+    },
+    /// A shell command whose output is the API token, e.g. `pass show cloudflare`.
+    Command { credential_command: String },
+    /// Configs written before the global key became optional have all three of `email`,
+    /// `api_token` and `api_key`, and are read as token credentials.
+    Plaintext(cloudflare_api::Credentials),
+    /// No credentials saved, they must be given in the environment.
+    Environment {},
+}
+
+impl StoredCredentials {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            StoredCredentials::Encrypted { .. } => "encrypted",
+            StoredCredentials::Command { .. } => "command",
+            StoredCredentials::Plaintext(cloudflare_api::Credentials::ApiToken { .. }) => {
+                "api token"
+            }
+            StoredCredentials::Plaintext(cloudflare_api::Credentials::GlobalApiKey { .. }) => {
+                "global api key"
+            }
+            StoredCredentials::Environment {} => "environment",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
 pub struct ClientConfig {
-    pub email: String,
-    pub api_token: String,
-    pub api_key: String,
+    #[serde(flatten)]
+    pub credentials: StoredCredentials,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_zone: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_base_url: Option<String>,
+    /// How many times a request is sent before giving up.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_attempts: Option<u32>,
+    /// Timeout of each request, in seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_seconds: Option<u64>,
+    /// Also retry requests that create rules or addresses after server errors and timeouts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_non_idempotent: Option<bool>,
+    /// Defaults of the aliases generated by `create`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alias: Option<alias::AliasOptions>,
+    /// Whether the profile was picked with `--profile`, so its saved credentials win over the
+    /// ones in the environment.
+    #[serde(skip)]
+    pub explicit: bool,
+}
+
+impl ClientConfig {
+    pub fn new(credentials: StoredCredentials) -> Self {
+        ClientConfig {
+            credentials,
+            default_zone: None,
+            api_base_url: None,
+            max_attempts: None,
+            timeout_seconds: None,
+            retry_non_idempotent: None,
+            alias: None,
+            explicit: false,
+        }
+    }
+
+    /// The credentials to use, asking for the passphrase if they're encrypted and locked.
+    ///
+    /// Credentials in the environment take precedence over the saved ones, unless the profile
+    /// was picked explicitly, as they may belong to another account.
+    pub fn credentials(&self) -> anyhow::Result<cloudflare_api::Credentials> {
+        let saved = !matches!(self.credentials, StoredCredentials::Environment {});
+        if self.explicit && saved {
+            let ignored = [API_TOKEN_ENV, EMAIL_ENV, API_KEY_ENV]
+                .into_iter()
+                .filter(|name| std::env::var_os(name).is_some_and(|value| !value.is_empty()))
+                .collect::<Vec<_>>();
+            if !ignored.is_empty() {
+                eprintln!(
+                    "Warning: ignoring {} in favor of the credentials of the given profile",
+                    ignored.join(", ")
+                );
+            }
+        } else if let Some(credentials) = credentials_from_env()? {
+            return Ok(credentials);
+        }
+
+        match &self.credentials {
+            StoredCredentials::Encrypted {
+                encrypted_credentials,
+            } => secrets::unlock(encrypted_credentials),
+            StoredCredentials::Command { credential_command } => {
+                run_credential_command(credential_command)
+            }
+            StoredCredentials::Plaintext(credentials) => Ok(credentials.clone()),
+            StoredCredentials::Environment {} => bail!(
+                "No credentials found. Please run the setup command first or set {API_TOKEN_ENV}."
+            ),
+        }
+    }
+
+    pub fn client_options(&self) -> cloudflare_api::ClientOptions {
+        let mut options = cloudflare_api::ClientOptions::default();
+
+        if let Some(base_url) = &self.api_base_url {
+            options.base_url = base_url.clone();
+        }
+        if let Some(max_attempts) = self.max_attempts {
+            options.max_attempts = max_attempts.max(1);
+        }
+        if let Some(timeout_seconds) = self.timeout_seconds {
+            options.timeout = Duration::from_secs(timeout_seconds);
+        }
+        if let Some(retry_non_idempotent) = self.retry_non_idempotent {
+            options.retry_non_idempotent = retry_non_idempotent;
+        }
+
+        apply_environment(&mut options);
+        options
+    }
+}
+
+/// Options of a client for when there's no config yet.
+pub fn default_client_options() -> cloudflare_api::ClientOptions {
+    let mut options = cloudflare_api::ClientOptions::default();
+    apply_environment(&mut options);
+    options
+}
+
+fn apply_environment(options: &mut cloudflare_api::ClientOptions) {
+    if let Ok(base_url) = std::env::var(API_BASE_URL_ENV) {
+        options.base_url = base_url;
+    }
+}
+
+fn credentials_from_env() -> anyhow::Result<Option<cloudflare_api::Credentials>> {
+    let var = |name| {
+        std::env::var(name)
+            .ok()
+            .filter(|value: &String| !value.is_empty())
+    };
+
+    if let Some(api_token) = var(API_TOKEN_ENV) {
+        return Ok(Some(cloudflare_api::Credentials::ApiToken { api_token }));
+    }
+
+    match (var(EMAIL_ENV), var(API_KEY_ENV)) {
+        (Some(email), Some(api_key)) => Ok(Some(cloudflare_api::Credentials::GlobalApiKey {
+            email,
+            api_key,
+        })),
+        (None, None) => Ok(None),
+        _ => bail!("{EMAIL_ENV} and {API_KEY_ENV} must be set together"),
+    }
+}
+
+/// Runs the command with the shell, keeping stdin and stderr so it can prompt, e.g. for a GPG
+/// passphrase.
+fn run_credential_command(command: &str) -> anyhow::Result<cloudflare_api::Credentials> {
+    let (shell, flag) = if cfg!(windows) {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+
+    let output = Command::new(shell)
+        .args([flag, command])
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .with_context(|| format!("Failed to run the credential command `{command}`"))?;
+
+    if !output.status.success() {
+        bail!(
+            "The credential command `{command}` failed with {}",
+            output.status
+        );
+    }
+
+    let api_token = String::from_utf8(output.stdout)
+        .with_context(|| format!("The credential command `{command}` printed invalid UTF-8"))?
+        .trim()
+        .to_string();
+    if api_token.is_empty() {
+        bail!("The credential command `{command}` didn't print a token");
+    }
+
+    Ok(cloudflare_api::Credentials::ApiToken { api_token })
 }
 
 pub fn get_config_path() -> anyhow::Result<std::path::PathBuf> {
@@ -16,18 +266,91 @@ pub fn get_config_path() -> anyhow::Result<std::path::PathBuf> {
         .join("config.toml"))
 }
 
-pub fn load_config() -> anyhow::Result<Option<ClientConfig>> {
+/// Reads the config file, or an empty config if there's none yet.
+pub fn load_config() -> anyhow::Result<Config> {
     let config_path = get_config_path()?;
 
     if !config_path.exists() || !config_path.is_file() {
-        return Ok(None);
+        return Ok(Config::default());
     }
 
     let config_content = std::fs::read_to_string(&config_path)
         .with_context(|| format!("Failed to read config at {config_path:?}"))?;
 
-    let config: ClientConfig = toml::from_str(&config_content)
+    let table: toml::Table = toml::from_str(&config_content)
         .with_context(|| format!("Failed to deserialize config at {config_path:?}"))?;
 
-    Ok(Some(config))
+    // configs written before profiles existed have the settings of a single client at the top
+    // level, they're saved in the new format the next time the config changes
+    let config = if table.is_empty()
+        || table.contains_key("profiles")
+        || table.contains_key("default_profile")
+    {
+        toml::Value::Table(table).try_into::<Config>()
+    } else {
+        toml::Value::Table(table)
+            .try_into::<ClientConfig>()
+            .map(|client_config| Config {
+                default_profile: None,
+                profiles: BTreeMap::from([(DEFAULT_PROFILE.to_string(), client_config)]),
+            })
+    }
+    .with_context(|| format!("Failed to deserialize config at {config_path:?}"))?;
+
+    Ok(config)
+}
+
+/// Loads the settings of the given profile, or of the default one.
+///
+/// Without a saved profile, the credentials in the environment are used if there are any.
+pub fn load_profile(profile: Option<&str>) -> anyhow::Result<Option<ClientConfig>> {
+    let mut config = load_config()?;
+    let name = config.profile_name(profile).to_string();
+
+    if let Some(mut client_config) = config.profiles.remove(&name) {
+        client_config.explicit = profile.is_some();
+        return Ok(Some(client_config));
+    }
+    if profile.is_some() {
+        bail!("Profile {name:?} not found. Create it with setup --profile {name}");
+    }
+
+    let client_config =
+        credentials_from_env()?.map(|_| ClientConfig::new(StoredCredentials::Environment {}));
+    Ok(client_config)
+}
+
+pub fn save_config(config: &Config) -> anyhow::Result<std::path::PathBuf> {
+    let config_path = get_config_path()?;
+
+    let config_content = toml::to_string(config).context("Failed to serialize config")?;
+
+    write_private_file(&config_path, config_content.as_bytes())
+        .with_context(|| format!("Failed to write config at {config_path:?}"))?;
+
+    Ok(config_path)
+}
+
+/// Writes a file only readable by the current user, as it may contain credentials.
+pub fn write_private_file(path: &Path, content: &[u8]) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        // the mode only applies to new files, fix the permissions of existing ones
+        if path.exists() {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+        }
+    }
+
+    options.open(path)?.write_all(content)?;
+
+    Ok(())
 }
@@ -0,0 +1,139 @@
+use crate::cloudflare_api::{EmailRoutingRule, EmailRoutingRuleActionType};
+use anyhow::Context;
+use regex::{Regex, RegexBuilder};
+use std::cmp::Reverse;
+
+/// Text to look for, ignoring case. Patterns with `*` or `?` are globs matching the whole text,
+/// the others match anywhere in it.
+#[derive(Debug)]
+pub enum Pattern {
+    Substring(String),
+    Glob(Regex),
+    Regex(Regex),
+}
+
+impl Pattern {
+    pub fn new(pattern: &str, regex: bool) -> anyhow::Result<Self> {
+        if regex {
+            let regex = RegexBuilder::new(pattern)
+                .case_insensitive(true)
+                .build()
+                .with_context(|| format!("Invalid regular expression {pattern:?}"))?;
+            return Ok(Pattern::Regex(regex));
+        }
+
+        if !pattern.contains(['*', '?']) {
+            return Ok(Pattern::Substring(pattern.to_lowercase()));
+        }
+
+        Pattern::glob(pattern)
+    }
+
+    /// A glob matching the whole text, even without wildcards.
+    pub fn glob(pattern: &str) -> anyhow::Result<Self> {
+        let glob = pattern
+            .chars()
+            .map(|char| match char {
+                '*' => ".*".to_string(),
+                '?' => ".".to_string(),
+                char => regex::escape(&char.to_string()),
+            })
+            .collect::<String>();
+        let regex = RegexBuilder::new(&format!("^{glob}$"))
+            .case_insensitive(true)
+            .build()
+            .with_context(|| format!("Invalid glob {pattern:?}"))?;
+
+        Ok(Pattern::Glob(regex))
+    }
+
+    pub fn matches(&self, text: &str) -> bool {
+        match self {
+            Pattern::Substring(substring) => text.to_lowercase().contains(substring),
+            Pattern::Glob(regex) | Pattern::Regex(regex) => regex.is_match(text),
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum ActionKind {
+    Forward,
+    Drop,
+    Worker,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default)]
+pub enum RuleSort {
+    /// Highest priority first
+    #[default]
+    Priority,
+    Matcher,
+    Name,
+}
+
+/// What a rule must have to be kept. Conditions that are `None` keep every rule.
+#[derive(Debug, Default)]
+pub struct RuleFilter {
+    pub matcher: Option<Pattern>,
+    /// Any of the addresses the rule forwards to.
+    pub destination: Option<Pattern>,
+    pub action: Option<ActionKind>,
+    pub enabled: Option<bool>,
+    pub name: Option<Pattern>,
+}
+
+impl RuleFilter {
+    pub fn matches(&self, rule: &EmailRoutingRule) -> bool {
+        let destinations = rule
+            .actions
+            .iter()
+            .flat_map(|action| match &action.action_type {
+                EmailRoutingRuleActionType::Forward { value } => value.as_slice(),
+                _ => &[],
+            })
+            .map(String::as_str);
+
+        any_matches(&self.matcher, rule.literal_matchers())
+            && any_matches(&self.destination, destinations)
+            && any_matches(&self.name, rule.name.as_deref())
+            && self.enabled.is_none_or(|enabled| rule.enabled == enabled)
+            && self.action.is_none_or(|kind| {
+                rule.actions
+                    .iter()
+                    .any(|action| kind.matches(&action.action_type))
+            })
+    }
+}
+
+impl ActionKind {
+    fn matches(self, action_type: &EmailRoutingRuleActionType) -> bool {
+        match action_type {
+            EmailRoutingRuleActionType::Forward { .. } => matches!(self, ActionKind::Forward),
+            EmailRoutingRuleActionType::Drop => matches!(self, ActionKind::Drop),
+            EmailRoutingRuleActionType::Worker { .. } => matches!(self, ActionKind::Worker),
+        }
+    }
+}
+
+fn any_matches<'a>(pattern: &Option<Pattern>, texts: impl IntoIterator<Item = &'a str>) -> bool {
+    pattern
+        .as_ref()
+        .is_none_or(|pattern| texts.into_iter().any(|text| pattern.matches(text)))
+}
+
+pub fn sort_rules(rules: &mut [EmailRoutingRule], sort: RuleSort) {
+    match sort {
+        RuleSort::Priority => rules.sort_by_key(|rule| Reverse(rule.priority.unwrap_or(0))),
+        RuleSort::Matcher => rules.sort_by_cached_key(|rule| {
+            rule.literal_matchers()
+                .next()
+                .map(str::to_lowercase)
+                .unwrap_or_default()
+        }),
+        // rules without a name last, the api gives them an empty one
+        RuleSort::Name => rules.sort_by_cached_key(|rule| {
+            let name = rule.name.as_deref().filter(|name| !name.is_empty());
+            (name.is_none(), name.map(str::to_lowercase))
+        }),
+    }
+}
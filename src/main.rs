@@ -1,63 +1,453 @@
-use clap::{Parser, Subcommand};
+use clap::{ArgGroup, Parser, Subcommand};
+use std::path::PathBuf;
+use std::process::ExitCode;
 
+mod alias;
+mod backup;
 mod cloudflare_api;
 mod command;
 mod config;
+mod filter;
+mod manifest;
+#[cfg(feature = "mock-server")]
+mod mock_server;
+mod output;
+mod secrets;
+mod state;
 
 #[derive(Parser, Debug)]
 #[command(about, long_about = None)]
 struct Args {
+    #[command(flatten)]
+    global: GlobalArgs,
     #[command(subcommand)]
     command: Command,
 }
 
+#[derive(clap::Args, Debug)]
+pub struct GlobalArgs {
+    /// Zone to operate on, by name or id. Defaults to the zone saved in the config.
+    #[arg(long, global = true)]
+    pub zone: Option<String>,
+    /// Format of the data printed to stdout. Status messages are always printed to stderr.
+    #[arg(long, short, global = true, value_enum, default_value_t)]
+    pub output: output::OutputFormat,
+    /// Profile of the config to use. Defaults to the profile selected with `profile use`.
+    #[arg(long, short, global = true)]
+    pub profile: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct CreateArgs {
+    pub matcher: Option<cloudflare_api::EmailRoutingRuleMatcher>,
+    pub action: Option<cloudflare_api::EmailRoutingRuleAction>,
+    #[arg(long)]
+    pub name: Option<String>,
+    #[arg(long)]
+    pub priority: Option<usize>,
+    /// Create the rule disabled
+    #[arg(long)]
+    pub disabled: bool,
+    /// Replace the rule of the matcher if it already exists
+    #[arg(long)]
+    pub force: bool,
+    /// Remember to delete the rule after this long, e.g. `7d` or `12h`. See the sweep command.
+    #[arg(long, value_parser = humantime::parse_duration)]
+    pub ttl: Option<std::time::Duration>,
+    /// Tag to find the rule with `list --tag`, saved locally. Can be repeated.
+    #[arg(long = "tag")]
+    pub tags: Vec<String>,
+    /// Note about the rule, saved locally
+    #[arg(long)]
+    pub note: Option<String>,
+    /// How to generate the alias when no matcher is given
+    #[command(flatten)]
+    pub alias: alias::AliasOptions,
+}
+
+/// Text filters ignore case. They match anywhere in the text, or the whole text if they have `*`
+/// or `?` wildcards.
+#[derive(clap::Args, Debug)]
+pub struct ListArgs {
+    /// Only the rules with a matching matcher
+    pub matcher: Option<String>,
+    /// Only the rules forwarding to a matching address
+    #[arg(long)]
+    pub destination: Option<String>,
+    /// Only the rules with a matching name
+    #[arg(long)]
+    pub name: Option<String>,
+    /// Treat the text filters as regular expressions
+    #[arg(long)]
+    pub regex: bool,
+    /// Only the rules with an action of this type
+    #[arg(long, value_enum)]
+    pub action: Option<filter::ActionKind>,
+    /// Only the enabled, or disabled, rules
+    #[arg(long)]
+    pub enabled: Option<bool>,
+    /// Only the rules created with this tag
+    #[arg(long)]
+    pub tag: Option<String>,
+    #[arg(long, value_enum, default_value_t)]
+    pub sort: filter::RuleSort,
+    /// List at most this many rules
+    #[arg(long)]
+    pub limit: Option<usize>,
+}
+
 #[derive(Subcommand, Debug)]
 enum Command {
+    /// Save the credentials to use, after verifying them. Use either an API token (recommended) or
+    /// the account's email and Global API Key.
+    #[command(group(ArgGroup::new("credentials").required(true).args(["api_token", "api_key"])))]
     Setup {
-        email: String,
-        api_token: String,
-        api_key: String,
+        /// API token with the permissions listed in the README
+        #[arg(long, conflicts_with_all = ["email", "api_key"])]
+        api_token: Option<String>,
+        /// Email of the Cloudflare account, used with --api-key
+        #[arg(long, requires = "api_key")]
+        email: Option<String>,
+        /// Global API Key of the account, used with --email
+        #[arg(long, requires = "email")]
+        api_key: Option<String>,
+        /// Encrypt the credentials with a passphrase, asked when they're needed
+        #[arg(long)]
+        encrypt: bool,
     },
-    List,
-    Addresses,
-    Zones,
-    Create {
+    /// Forget the encrypted credentials unlocked in this session
+    Lock,
+    /// Manage the profiles of the config, e.g. one per Cloudflare account
+    Profile {
+        #[command(subcommand)]
+        command: Option<ProfileCommand>,
+    },
+    /// List the rules, optionally only those matching all the given filters
+    List(ListArgs),
+    Addresses {
+        #[command(subcommand)]
+        command: Option<AddressesCommand>,
+    },
+    Zones {
+        /// Save the given zone (name or id) as the default zone
+        #[arg(long)]
+        set_default: Option<String>,
+    },
+    Create(Box<CreateArgs>),
+    Delete {
+        #[arg(
+            required_unless_present = "all_matching",
+            conflicts_with = "all_matching"
+        )]
+        identifier: Option<String>,
+        /// Delete every rule whose whole matcher matches the glob, e.g. `test-*@example.com`
+        #[arg(long, value_name = "PATTERN")]
+        all_matching: Option<String>,
+        /// Treat the pattern of --all-matching as a regular expression, matching anywhere in the matcher
+        #[arg(long, requires = "all_matching")]
+        regex: bool,
+        /// Don't ask for confirmation before deleting the rules matched by --all-matching
+        #[arg(long, short, requires = "all_matching")]
+        yes: bool,
+    },
+    /// Show everything about a rule, including whether its destinations are verified
+    Show {
+        identifier: String,
+    },
+    /// Delete the rules created with a --ttl that has passed, e.g. from a cron job
+    Sweep {
+        /// Disable the expired rules instead of deleting them
+        #[arg(long)]
+        disable: bool,
+        /// Only show the expired rules
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Update an existing rule, keeping the fields that aren't specified
+    Update {
+        identifier: String,
+        #[arg(long)]
         matcher: Option<cloudflare_api::EmailRoutingRuleMatcher>,
-        action: Option<cloudflare_api::EmailRoutingRuleAction>,
+        /// Replaces all the actions of the rule. Can be repeated.
+        #[arg(long = "action")]
+        actions: Vec<cloudflare_api::EmailRoutingRuleAction>,
         #[arg(long)]
         name: Option<String>,
         #[arg(long)]
         priority: Option<usize>,
+        #[arg(long)]
+        enabled: Option<bool>,
     },
-    Delete {
+    /// Manage the rule for the emails that don't match any other rule
+    CatchAll {
+        #[command(subcommand)]
+        command: CatchAllCommand,
+    },
+    /// Manage email routing itself on the zone
+    Routing {
+        #[command(subcommand)]
+        command: RoutingCommand,
+    },
+    /// Show the changes needed to make the zones match a manifest
+    Plan {
+        manifest: PathBuf,
+        /// Also delete the rules that aren't in the manifest
+        #[arg(long)]
+        prune: bool,
+    },
+    /// Make the zones match a manifest
+    Apply {
+        manifest: PathBuf,
+        /// Also delete the rules that aren't in the manifest
+        #[arg(long)]
+        prune: bool,
+    },
+    /// Save the rules and destination addresses of a zone to a file
+    Export {
+        file: PathBuf,
+        /// Defaults to the format matching the file extension, or json
+        #[arg(long, value_enum)]
+        format: Option<backup::BackupFormat>,
+    },
+    /// Recreate the rules and destination addresses of an exported file in a zone
+    Import {
+        file: PathBuf,
+        /// Defaults to the format matching the file extension, or json
+        #[arg(long, value_enum)]
+        format: Option<backup::BackupFormat>,
+    },
+    /// Enable a rule
+    Enable {
+        identifier: String,
+    },
+    /// Disable a rule without deleting it
+    Disable {
         identifier: String,
     },
+    /// Serve an in-memory imitation of the Cloudflare API, printing its url
+    #[cfg(feature = "mock-server")]
+    #[command(hide = true)]
+    MockServer {
+        #[arg(long, default_value = "127.0.0.1:0")]
+        address: String,
+        #[command(flatten)]
+        options: mock_server::MockOptions,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum AddressesCommand {
+    /// List the destination addresses (default)
+    List,
+    /// Add a destination address. Cloudflare sends it an email to verify it.
+    Add { email: String },
+    /// Remove a destination address
+    Remove { identifier: String },
+    /// Show a destination address and whether it's verified
+    Show { identifier: String },
+}
+
+#[derive(Subcommand, Debug)]
+enum ProfileCommand {
+    /// List the profiles (default)
+    List,
+    /// Use the given profile when --profile isn't given
+    Use { name: String },
+    /// Remove a profile and its credentials
+    Remove { name: String },
+}
+
+#[derive(Subcommand, Debug)]
+enum CatchAllCommand {
+    /// Show the catch-all rule
+    Show,
+    /// Forward the emails to the given addresses
+    Forward {
+        #[arg(required = true)]
+        addresses: Vec<String>,
+    },
+    /// Drop the emails
+    Drop,
+    /// Send the emails to a worker
+    Worker { name: String },
+    /// Enable the catch-all rule with its current actions
+    Enable,
+    /// Disable the catch-all rule
+    Disable,
+}
+
+#[derive(Subcommand, Debug)]
+enum RoutingCommand {
+    /// Show whether email routing is enabled and ready
+    Status,
+    /// Enable email routing, adding the required DNS records
+    Enable,
+    /// Disable email routing. The rules are kept but no emails are routed.
+    Disable,
+    /// Show the DNS records (MX, SPF, DKIM) required by email routing
+    Dns,
 }
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
+async fn main() -> ExitCode {
     let args = Args::parse();
 
+    match run(args).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("Error: {error:?}");
+            ExitCode::from(exit_code(&error))
+        }
+    }
+}
+
+/// Exit code of a failed command, so scripts can tell the causes apart. `2` is used by clap for
+/// invalid arguments.
+fn exit_code(error: &anyhow::Error) -> u8 {
+    use cloudflare_api::Error;
+    use reqwest::StatusCode;
+
+    let Some(error) = error.downcast_ref::<Error>() else {
+        return 1;
+    };
+
+    if error.is_authentication_error() {
+        return 4;
+    }
+
+    match error {
+        Error::Transport(_) => 3,
+        Error::Status { status, .. } => match *status {
+            StatusCode::NOT_FOUND => 5,
+            StatusCode::TOO_MANY_REQUESTS => 6,
+            status if status.is_server_error() => 7,
+            _ => 8,
+        },
+        Error::Api { .. } => 8,
+        Error::Deserialize { .. } | Error::MissingResult => 9,
+    }
+}
+
+async fn run(args: Args) -> anyhow::Result<()> {
+    let global = args.global;
+
     match args.command {
         Command::Setup {
-            email,
             api_token,
+            email,
             api_key,
+            encrypt,
         } => {
-            command::handle_setup(email, api_token, api_key).await?;
+            let credentials = match (api_token, email, api_key) {
+                (Some(api_token), _, _) => cloudflare_api::Credentials::ApiToken { api_token },
+                (None, Some(email), Some(api_key)) => {
+                    cloudflare_api::Credentials::GlobalApiKey { email, api_key }
+                }
+                _ => unreachable!("clap requires either --api-token or --email and --api-key"),
+            };
+            command::handle_setup(&global, credentials, encrypt).await?;
+        }
+        Command::Lock => command::handle_lock()?,
+        Command::Profile { command } => match command.unwrap_or(ProfileCommand::List) {
+            ProfileCommand::List => command::handle_list_profiles(&global)?,
+            ProfileCommand::Use { name } => command::handle_use_profile(name)?,
+            ProfileCommand::Remove { name } => command::handle_remove_profile(name)?,
+        },
+        Command::List(args) => command::handle_list_rules(&global, args).await?,
+        Command::Addresses { command } => match command.unwrap_or(AddressesCommand::List) {
+            AddressesCommand::List => command::handle_list_addresses(&global).await?,
+            AddressesCommand::Add { email } => command::handle_add_address(&global, email).await?,
+            AddressesCommand::Remove { identifier } => {
+                command::handle_remove_address(&global, identifier).await?;
+            }
+            AddressesCommand::Show { identifier } => {
+                command::handle_show_address(&global, identifier).await?;
+            }
+        },
+        Command::Create(args) => command::handle_create_rule(&global, *args).await?,
+        Command::Delete {
+            identifier,
+            all_matching,
+            regex,
+            yes,
+        } => match (identifier, all_matching) {
+            (_, Some(pattern)) => {
+                command::handle_delete_matching_rules(&global, pattern, regex, yes).await?;
+            }
+            (Some(identifier), None) => command::handle_delete_rule(&global, identifier).await?,
+            (None, None) => unreachable!("clap requires one of them"),
+        },
+        Command::Show { identifier } => command::handle_show_rule(&global, identifier).await?,
+        Command::Sweep { disable, dry_run } => {
+            command::handle_sweep(&global, disable, dry_run).await?;
         }
-        Command::List => command::handle_list_rules().await?,
-        Command::Addresses => command::handle_list_addresses().await?,
-        Command::Create {
+        Command::Update {
+            identifier,
             matcher,
-            action,
+            actions,
             name,
             priority,
-        } => command::handle_create_rule(matcher, action, name, priority).await?,
-        Command::Delete { identifier } => {
-            command::handle_delete_rule(identifier).await?;
+            enabled,
+        } => {
+            command::handle_update_rule(
+                &global, identifier, matcher, actions, name, priority, enabled,
+            )
+            .await?;
+        }
+        Command::CatchAll { command } => {
+            use cloudflare_api::{EmailRoutingRuleAction, EmailRoutingRuleActionType};
+
+            let action = |action_type| Some(vec![EmailRoutingRuleAction { action_type }]);
+
+            match command {
+                CatchAllCommand::Show => command::handle_show_catch_all(&global).await?,
+                CatchAllCommand::Forward { addresses } => {
+                    let actions = action(EmailRoutingRuleActionType::Forward { value: addresses });
+                    command::handle_set_catch_all(&global, actions, true).await?;
+                }
+                CatchAllCommand::Drop => {
+                    let actions = action(EmailRoutingRuleActionType::Drop);
+                    command::handle_set_catch_all(&global, actions, true).await?;
+                }
+                CatchAllCommand::Worker { name } => {
+                    let actions = action(EmailRoutingRuleActionType::Worker { value: vec![name] });
+                    command::handle_set_catch_all(&global, actions, true).await?;
+                }
+                CatchAllCommand::Enable => {
+                    command::handle_set_catch_all(&global, None, true).await?;
+                }
+                CatchAllCommand::Disable => {
+                    command::handle_set_catch_all(&global, None, false).await?;
+                }
+            }
+        }
+        Command::Routing { command } => match command {
+            RoutingCommand::Status => command::handle_routing_status(&global).await?,
+            RoutingCommand::Enable => command::handle_set_routing_enabled(&global, true).await?,
+            RoutingCommand::Disable => command::handle_set_routing_enabled(&global, false).await?,
+            RoutingCommand::Dns => command::handle_routing_dns(&global).await?,
         },
-        Command::Zones => command::handle_list_zones().await?,
+        Command::Plan { manifest, prune } => {
+            command::handle_plan(&global, manifest, prune).await?;
+        }
+        Command::Apply { manifest, prune } => {
+            command::handle_apply(&global, manifest, prune).await?;
+        }
+        Command::Export { file, format } => command::handle_export(&global, file, format).await?,
+        Command::Import { file, format } => {
+            command::handle_import(&global, file, format).await?;
+        }
+        Command::Enable { identifier } => {
+            command::handle_set_rule_enabled(&global, identifier, true).await?;
+        }
+        Command::Disable { identifier } => {
+            command::handle_set_rule_enabled(&global, identifier, false).await?;
+        }
+        Command::Zones { set_default } => command::handle_list_zones(&global, set_default).await?,
+        #[cfg(feature = "mock-server")]
+        Command::MockServer { address, options } => {
+            mock_server::serve(&address, &options).await?;
+        }
     }
 
     Ok(())
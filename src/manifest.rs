@@ -0,0 +1,245 @@
+use crate::cloudflare_api::{
+    Address, CreateRoutingRuleRequest, EmailRoutingRule, EmailRoutingRuleAction,
+    EmailRoutingRuleMatcher,
+};
+use crate::output::join_display;
+use anyhow::{Context, bail};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashSet;
+use std::path::Path;
+
+/// Desired state of the email routing of one or more zones, usually kept in git.
+///
+/// ```toml
+/// [[zones]]
+/// zone = "mail.com"
+/// destinations = ["mypersonalemail@mail.com"]
+///
+/// [[zones.rules]]
+/// matcher = "shopping"
+/// actions = ["mypersonalemail@mail.com"]
+/// name = "Online shopping"
+/// ```
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    #[serde(default)]
+    pub zones: Vec<ZoneManifest>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ZoneManifest {
+    /// Name or id of the zone. Defaults to the zone selected with `--zone` or saved in the config.
+    pub zone: Option<String>,
+    /// Destination addresses that must exist in the zone's account.
+    #[serde(default)]
+    pub destinations: Vec<String>,
+    #[serde(default)]
+    pub rules: Vec<RuleManifest>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RuleManifest {
+    /// The email address, or just its username to use the zone's domain.
+    pub matcher: String,
+    /// Same syntax as the `create` command: an address to forward to, `drop` or `worker:<name>`.
+    #[serde(deserialize_with = "deserialize_actions")]
+    pub actions: Vec<EmailRoutingRuleAction>,
+    /// Left as is when omitted.
+    pub name: Option<String>,
+    /// Left as is when omitted.
+    pub priority: Option<usize>,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn deserialize_actions<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<EmailRoutingRuleAction>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|action| action.parse().map_err(serde::de::Error::custom))
+        .collect()
+}
+
+pub fn load_manifest(path: &Path) -> anyhow::Result<Manifest> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read manifest at {path:?}"))?;
+
+    toml::from_str(&content).with_context(|| format!("Failed to deserialize manifest at {path:?}"))
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "operation", rename_all = "kebab-case")]
+pub enum Operation {
+    CreateDestination {
+        email: String,
+    },
+    CreateRule {
+        rule: CreateRoutingRuleRequest,
+    },
+    UpdateRule {
+        id: String,
+        rule: CreateRoutingRuleRequest,
+        /// Human readable description of the fields that change.
+        changes: Vec<String>,
+    },
+    DeleteRule {
+        rule: EmailRoutingRule,
+    },
+}
+
+/// An operation of a zone's plan, as it's shown to the user.
+#[derive(Serialize, Debug)]
+pub struct PlannedOperation<'a> {
+    pub zone: &'a str,
+    #[serde(flatten)]
+    pub operation: &'a Operation,
+}
+
+pub struct ZonePlan {
+    pub operations: Vec<Operation>,
+    /// Existing rules that aren't in the manifest and are kept because pruning is disabled.
+    pub unmanaged: Vec<EmailRoutingRule>,
+}
+
+pub fn qualify_matcher(matcher: &str, domain: &str) -> String {
+    if matcher.contains('@') {
+        matcher.to_string()
+    } else {
+        format!("{matcher}@{domain}")
+    }
+}
+
+fn describe_change(field: &str, old: String, new: String) -> Option<String> {
+    (old != new).then(|| format!("{field}: {old} -> {new}"))
+}
+
+/// Computes the operations needed to make a zone match its manifest.
+///
+/// Rules are matched by their email address, and the catch-all rule is never touched.
+pub fn plan_zone(
+    manifest: &ZoneManifest,
+    domain: &str,
+    mut rules: Vec<EmailRoutingRule>,
+    addresses: &[Address],
+    prune: bool,
+) -> anyhow::Result<ZonePlan> {
+    let mut operations = vec![];
+
+    for destination in &manifest.destinations {
+        let exists = addresses.iter().any(|address| {
+            address
+                .email
+                .as_ref()
+                .is_some_and(|email| email.eq_ignore_ascii_case(destination))
+        });
+        if !exists {
+            operations.push(Operation::CreateDestination {
+                email: destination.clone(),
+            });
+        }
+    }
+
+    rules.retain(|rule| !rule.is_catch_all());
+
+    let mut seen_matchers = HashSet::new();
+    for desired in &manifest.rules {
+        if desired.matcher == "*" {
+            bail!("The catch-all rule can't be managed from a manifest, use the catch-all command");
+        }
+
+        let matcher = qualify_matcher(&desired.matcher, domain);
+        if !seen_matchers.insert(matcher.to_lowercase()) {
+            bail!("Rule {matcher} is defined more than once in the manifest");
+        }
+
+        let matchers = vec![EmailRoutingRuleMatcher::literal(matcher.clone())];
+
+        let existing = rules.iter().position(|rule| {
+            rule.literal_matchers()
+                .any(|value| value.eq_ignore_ascii_case(&matcher))
+        });
+
+        let Some(existing) = existing else {
+            operations.push(Operation::CreateRule {
+                rule: CreateRoutingRuleRequest {
+                    actions: desired.actions.clone(),
+                    matchers,
+                    enabled: Some(desired.enabled),
+                    name: desired.name.clone(),
+                    priority: desired.priority,
+                },
+            });
+            continue;
+        };
+
+        let existing = rules.swap_remove(existing);
+        let name = desired.name.clone().or(existing.name.clone());
+        let priority = desired.priority.or(existing.priority);
+
+        let changes = [
+            describe_change(
+                "matchers",
+                join_display(&existing.matchers),
+                join_display(&matchers),
+            ),
+            describe_change(
+                "actions",
+                join_display(&existing.actions),
+                join_display(&desired.actions),
+            ),
+            describe_change(
+                "name",
+                existing.name.clone().unwrap_or_default(),
+                name.clone().unwrap_or_default(),
+            ),
+            describe_change(
+                "priority",
+                existing.priority.unwrap_or(0).to_string(),
+                priority.unwrap_or(0).to_string(),
+            ),
+            describe_change(
+                "enabled",
+                existing.enabled.to_string(),
+                desired.enabled.to_string(),
+            ),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+
+        if !changes.is_empty() {
+            operations.push(Operation::UpdateRule {
+                id: existing.id,
+                rule: CreateRoutingRuleRequest {
+                    actions: desired.actions.clone(),
+                    matchers,
+                    enabled: Some(desired.enabled),
+                    name,
+                    priority,
+                },
+                changes,
+            });
+        }
+    }
+
+    // whatever wasn't matched by the manifest is unmanaged
+    let unmanaged = if prune {
+        operations.extend(rules.into_iter().map(|rule| Operation::DeleteRule { rule }));
+        vec![]
+    } else {
+        rules
+    };
+
+    Ok(ZonePlan {
+        operations,
+        unmanaged,
+    })
+}
@@ -0,0 +1,667 @@
+//! In-memory implementation of the part of the Cloudflare API used by [`cloudflare_api::Client`],
+//! to exercise the commands without a Cloudflare account.
+//!
+//! Start it with the `mock-server` command and point the tool to the printed address with the
+//! `CF_API_BASE_URL` environment variable.
+
+use crate::cloudflare_api::{
+    self, Address, DnsRecord, EmailRoutingRule, EmailRoutingRuleAction, EmailRoutingRuleMatcher,
+    EmailRoutingRuleMatcherType, EmailRoutingSettings, EmailRoutingStatus, Zone, ZoneAccount,
+};
+use anyhow::Context;
+use axum::extract::{Path, Query, Request, State};
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::{Arc, Mutex};
+
+const ACCOUNT_ID: &str = "mock-account";
+const TIMESTAMP: &str = "2024-01-01T00:00:00Z";
+/// Destination address that is created verified, so rules can forward to it.
+pub const VERIFIED_ADDRESS: &str = "me@example.org";
+
+/// What the mock serves, and how it imitates the less common behaviors of the real api.
+#[derive(clap::Args, Debug, Clone)]
+pub struct MockOptions {
+    /// Zones to create. Can be repeated.
+    #[arg(long = "zone-name", default_value = "example.com")]
+    pub zone_names: Vec<String>,
+    /// Reject the first requests as rate limited
+    #[arg(long, default_value_t = 0)]
+    pub rate_limited_requests: usize,
+    /// Seconds the rate limited requests are asked to wait, with the `Retry-After` header
+    #[arg(long, default_value_t = 1)]
+    pub retry_after: u64,
+    /// Which totals the pages of the lists report
+    #[arg(long, value_enum, default_value = "total-pages")]
+    pub page_info: PageInfo,
+}
+
+/// Not every endpoint of the real api reports the same totals in its pages.
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum PageInfo {
+    /// `total_pages` and `total_count`
+    TotalPages,
+    /// Only `total_count`
+    TotalCount,
+    /// Only the `count` of the page
+    Count,
+}
+
+struct MockZone {
+    zone: Zone,
+    settings: EmailRoutingSettings,
+    rules: Vec<EmailRoutingRule>,
+    catch_all: EmailRoutingRule,
+}
+
+struct MockState {
+    zones: Vec<MockZone>,
+    addresses: Vec<Address>,
+    next_id: u64,
+    /// Number of upcoming requests to reject with `429 Too Many Requests`.
+    rate_limited_requests: usize,
+    retry_after: u64,
+    page_info: PageInfo,
+}
+
+impl MockState {
+    fn new(options: &MockOptions) -> Self {
+        let mut state = MockState {
+            zones: vec![],
+            addresses: vec![],
+            next_id: 1,
+            rate_limited_requests: options.rate_limited_requests,
+            retry_after: options.retry_after,
+            page_info: options.page_info,
+        };
+
+        for name in &options.zone_names {
+            let zone_id = state.generate_id();
+            let catch_all_id = state.generate_id();
+            state.zones.push(MockZone {
+                zone: Zone {
+                    id: zone_id.clone(),
+                    name: name.clone(),
+                    account: ZoneAccount {
+                        id: ACCOUNT_ID.to_string(),
+                        name: "Mock Account".to_string(),
+                    },
+                },
+                settings: EmailRoutingSettings {
+                    id: zone_id.clone(),
+                    enabled: true,
+                    name: name.clone(),
+                    created: Some(TIMESTAMP.to_string()),
+                    modified: Some(TIMESTAMP.to_string()),
+                    status: Some(EmailRoutingStatus::Ready),
+                },
+                rules: vec![],
+                catch_all: EmailRoutingRule {
+                    tag: Some(catch_all_id.clone()),
+                    id: catch_all_id,
+                    actions: vec![EmailRoutingRuleAction {
+                        action_type: cloudflare_api::EmailRoutingRuleActionType::Drop,
+                    }],
+                    enabled: false,
+                    matchers: vec![EmailRoutingRuleMatcher {
+                        matcher_type: EmailRoutingRuleMatcherType::All,
+                    }],
+                    name: Some(String::new()),
+                    priority: None,
+                },
+            });
+        }
+
+        let address_id = state.generate_id();
+        state.addresses.push(Address {
+            id: Some(address_id),
+            created: Some(TIMESTAMP.to_string()),
+            email: Some(VERIFIED_ADDRESS.to_string()),
+            modified: Some(TIMESTAMP.to_string()),
+            tag: None,
+            verified: Some(TIMESTAMP.to_string()),
+        });
+
+        state
+    }
+
+    fn generate_id(&mut self) -> String {
+        self.next_id += 1;
+        format!("{:032x}", self.next_id)
+    }
+
+    fn zone_mut(&mut self, zone_id: &str) -> Result<&mut MockZone, ApiError> {
+        self.zones
+            .iter_mut()
+            .find(|zone| zone.zone.id == zone_id)
+            .ok_or_else(|| {
+                error(
+                    StatusCode::NOT_FOUND,
+                    7003,
+                    "Could not route to the zone, perhaps your object identifier is invalid?",
+                )
+            })
+    }
+}
+
+type SharedState = Arc<Mutex<MockState>>;
+type HandlerResult = Result<Response, ApiError>;
+
+/// Error in the shape of the Cloudflare api errors.
+struct ApiError {
+    status: StatusCode,
+    code: usize,
+    message: String,
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = json!({
+            "success": false,
+            "errors": [{ "code": self.code, "message": self.message }],
+            "messages": [],
+            "result": null,
+        });
+        (self.status, Json(body)).into_response()
+    }
+}
+
+#[derive(Deserialize)]
+struct PageQuery {
+    page: Option<usize>,
+    per_page: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct RuleBody {
+    #[serde(default)]
+    actions: Vec<EmailRoutingRuleAction>,
+    #[serde(default)]
+    matchers: Vec<EmailRoutingRuleMatcher>,
+    enabled: Option<bool>,
+    name: Option<String>,
+    priority: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct AddressBody {
+    email: String,
+}
+
+fn success<T: Serialize>(result: T) -> HandlerResult {
+    Ok(Json(json!({
+        "success": true,
+        "errors": [],
+        "messages": [],
+        "result": result,
+    }))
+    .into_response())
+}
+
+fn paginated<T: Serialize>(items: &[T], query: &PageQuery, page_info: PageInfo) -> HandlerResult {
+    let page = query.page.unwrap_or(1).max(1);
+    let per_page = query.per_page.unwrap_or(20).clamp(1, 50);
+    let page_items = items
+        .iter()
+        .skip((page - 1) * per_page)
+        .take(per_page)
+        .collect::<Vec<_>>();
+
+    let mut result_info = json!({
+        "page": page,
+        "per_page": per_page,
+        "count": page_items.len(),
+    });
+    if matches!(page_info, PageInfo::TotalPages | PageInfo::TotalCount) {
+        result_info["total_count"] = json!(items.len());
+    }
+    if matches!(page_info, PageInfo::TotalPages) {
+        result_info["total_pages"] = json!(items.len().div_ceil(per_page));
+    }
+
+    Ok(Json(json!({
+        "success": true,
+        "errors": [],
+        "messages": [],
+        "result": page_items,
+        "result_info": result_info,
+    }))
+    .into_response())
+}
+
+fn error(status: StatusCode, code: usize, message: &str) -> ApiError {
+    ApiError {
+        status,
+        code,
+        message: message.to_string(),
+    }
+}
+
+fn not_found(what: &str) -> ApiError {
+    error(StatusCode::NOT_FOUND, 1001, &format!("Unknown {what}"))
+}
+
+/// Like the real api, rejects the requests without any credentials.
+async fn require_credentials(request: Request, next: Next) -> Response {
+    let headers = request.headers();
+    if !headers.contains_key(header::AUTHORIZATION) && !headers.contains_key("X-Auth-Key") {
+        return error(StatusCode::BAD_REQUEST, 10000, "Authentication error").into_response();
+    }
+    next.run(request).await
+}
+
+/// Imitates the rate limiting of the real api, asking to retry after a while.
+async fn rate_limit(State(state): State<SharedState>, request: Request, next: Next) -> Response {
+    {
+        let mut state = state.lock().unwrap();
+        if state.rate_limited_requests > 0 {
+            state.rate_limited_requests -= 1;
+            let error = error(
+                StatusCode::TOO_MANY_REQUESTS,
+                971,
+                "Please wait and consider throttling your request speed",
+            );
+            let retry_after = state.retry_after.to_string();
+            return ([(header::RETRY_AFTER, retry_after)], error).into_response();
+        }
+    }
+    next.run(request).await
+}
+
+pub fn router(options: &MockOptions) -> Router {
+    let state: SharedState = Arc::new(Mutex::new(MockState::new(options)));
+
+    Router::new()
+        .route("/user", get(get_user))
+        .route("/user/tokens/verify", get(verify_token))
+        .route("/zones", get(list_zones))
+        .route("/zones/{zone_id}/email/routing", get(get_settings))
+        .route(
+            "/zones/{zone_id}/email/routing/enable",
+            post(enable_routing),
+        )
+        .route(
+            "/zones/{zone_id}/email/routing/disable",
+            post(disable_routing),
+        )
+        .route("/zones/{zone_id}/email/routing/dns", get(get_dns_records))
+        .route(
+            "/zones/{zone_id}/email/routing/rules",
+            get(list_rules).post(create_rule),
+        )
+        .route(
+            "/zones/{zone_id}/email/routing/rules/catch_all",
+            get(get_catch_all).put(update_catch_all),
+        )
+        .route(
+            "/zones/{zone_id}/email/routing/rules/{rule_id}",
+            get(get_rule).put(update_rule).delete(delete_rule),
+        )
+        .route(
+            "/accounts/{account_id}/email/routing/addresses",
+            get(list_addresses).post(create_address),
+        )
+        .route(
+            "/accounts/{account_id}/email/routing/addresses/{address_id}",
+            get(get_address).delete(delete_address),
+        )
+        .layer(middleware::from_fn(require_credentials))
+        .layer(middleware::from_fn_with_state(state.clone(), rate_limit))
+        .with_state(state)
+}
+
+/// Serves the mock api until the process is killed, printing the address it listens on.
+pub async fn serve(address: &str, options: &MockOptions) -> anyhow::Result<()> {
+    let listener = tokio::net::TcpListener::bind(address)
+        .await
+        .with_context(|| format!("Failed to bind to {address}"))?;
+
+    println!("Listening on http://{}", listener.local_addr()?);
+
+    axum::serve(listener, router(options))
+        .await
+        .context("Mock server failed")
+}
+
+async fn get_user(headers: HeaderMap) -> HandlerResult {
+    match headers.get("X-Auth-Email") {
+        Some(email) => {
+            success(json!({ "id": "mock-user", "email": email.to_str().unwrap_or_default() }))
+        }
+        None => Err(error(
+            StatusCode::BAD_REQUEST,
+            9106,
+            "Missing X-Auth-Email header",
+        )),
+    }
+}
+
+async fn verify_token(headers: HeaderMap) -> HandlerResult {
+    if !headers.contains_key(header::AUTHORIZATION) {
+        return Err(error(StatusCode::BAD_REQUEST, 1000, "Invalid API Token"));
+    }
+    success(json!({ "id": "mock-token", "status": "active" }))
+}
+
+async fn list_zones(
+    State(state): State<SharedState>,
+    Query(query): Query<PageQuery>,
+) -> HandlerResult {
+    let state = state.lock().unwrap();
+    let zones = state
+        .zones
+        .iter()
+        .map(|zone| &zone.zone)
+        .collect::<Vec<_>>();
+    paginated(&zones, &query, state.page_info)
+}
+
+async fn get_settings(
+    State(state): State<SharedState>,
+    Path(zone_id): Path<String>,
+) -> HandlerResult {
+    let mut state = state.lock().unwrap();
+    success(&state.zone_mut(&zone_id)?.settings)
+}
+
+async fn enable_routing(
+    State(state): State<SharedState>,
+    Path(zone_id): Path<String>,
+) -> HandlerResult {
+    let mut state = state.lock().unwrap();
+    let zone = state.zone_mut(&zone_id)?;
+    zone.settings.enabled = true;
+    zone.settings.status = Some(EmailRoutingStatus::Ready);
+    success(&zone.settings)
+}
+
+async fn disable_routing(
+    State(state): State<SharedState>,
+    Path(zone_id): Path<String>,
+) -> HandlerResult {
+    let mut state = state.lock().unwrap();
+    let zone = state.zone_mut(&zone_id)?;
+    zone.settings.enabled = false;
+    zone.settings.status = Some(EmailRoutingStatus::Unconfigured);
+    success(&zone.settings)
+}
+
+async fn get_dns_records(
+    State(state): State<SharedState>,
+    Path(zone_id): Path<String>,
+) -> HandlerResult {
+    let mut state = state.lock().unwrap();
+    let name = state.zone_mut(&zone_id)?.zone.name.clone();
+
+    let mx = |host: &str, priority| DnsRecord {
+        record_type: "MX".to_string(),
+        name: name.clone(),
+        content: host.to_string(),
+        priority: Some(priority),
+        ttl: Some(1),
+    };
+
+    success(vec![
+        mx("route1.mx.cloudflare.net", 13),
+        mx("route2.mx.cloudflare.net", 86),
+        mx("route3.mx.cloudflare.net", 24),
+        DnsRecord {
+            record_type: "TXT".to_string(),
+            name: name.clone(),
+            content: "v=spf1 include:_spf.mx.cloudflare.net ~all".to_string(),
+            priority: None,
+            ttl: Some(1),
+        },
+    ])
+}
+
+async fn list_rules(
+    State(state): State<SharedState>,
+    Path(zone_id): Path<String>,
+    Query(query): Query<PageQuery>,
+) -> HandlerResult {
+    let mut state = state.lock().unwrap();
+    let page_info = state.page_info;
+    paginated(&state.zone_mut(&zone_id)?.rules, &query, page_info)
+}
+
+fn validate_rule_body(
+    zone: &MockZone,
+    body: &RuleBody,
+    rule_id: Option<&str>,
+) -> Result<(), ApiError> {
+    if body.actions.is_empty() || body.matchers.is_empty() {
+        return Err(error(
+            StatusCode::BAD_REQUEST,
+            2007,
+            "Invalid rule: actions and matchers are required",
+        ));
+    }
+
+    for matcher in &body.matchers {
+        let EmailRoutingRuleMatcherType::Literal { value, .. } = &matcher.matcher_type else {
+            return Err(error(
+                StatusCode::BAD_REQUEST,
+                2007,
+                "Invalid rule: use the catch-all endpoint for the catch-all rule",
+            ));
+        };
+
+        let duplicate = zone.rules.iter().any(|rule| {
+            Some(rule.id.as_str()) != rule_id
+                && rule
+                    .literal_matchers()
+                    .any(|other| other.eq_ignore_ascii_case(value))
+        });
+        if duplicate {
+            return Err(error(StatusCode::CONFLICT, 2020, "Duplicated rule"));
+        }
+    }
+
+    Ok(())
+}
+
+async fn create_rule(
+    State(state): State<SharedState>,
+    Path(zone_id): Path<String>,
+    Json(body): Json<RuleBody>,
+) -> HandlerResult {
+    let mut state = state.lock().unwrap();
+    let id = state.generate_id();
+    let zone = state.zone_mut(&zone_id)?;
+    validate_rule_body(zone, &body, None)?;
+
+    let rule = EmailRoutingRule {
+        tag: Some(id.clone()),
+        id,
+        actions: body.actions,
+        enabled: body.enabled.unwrap_or(true),
+        matchers: body.matchers,
+        name: Some(body.name.unwrap_or_default()),
+        priority: Some(body.priority.unwrap_or(0)),
+    };
+
+    let response = success(&rule);
+    zone.rules.push(rule);
+    response
+}
+
+fn find_rule<'a>(
+    zone: &'a mut MockZone,
+    rule_id: &str,
+) -> Result<&'a mut EmailRoutingRule, ApiError> {
+    zone.rules
+        .iter_mut()
+        .find(|rule| rule.id == rule_id)
+        .ok_or_else(|| not_found("rule"))
+}
+
+async fn get_rule(
+    State(state): State<SharedState>,
+    Path((zone_id, rule_id)): Path<(String, String)>,
+) -> HandlerResult {
+    let mut state = state.lock().unwrap();
+    success(find_rule(state.zone_mut(&zone_id)?, &rule_id)?)
+}
+
+async fn update_rule(
+    State(state): State<SharedState>,
+    Path((zone_id, rule_id)): Path<(String, String)>,
+    Json(body): Json<RuleBody>,
+) -> HandlerResult {
+    let mut state = state.lock().unwrap();
+    let zone = state.zone_mut(&zone_id)?;
+    validate_rule_body(zone, &body, Some(&rule_id))?;
+
+    let rule = find_rule(zone, &rule_id)?;
+    rule.actions = body.actions;
+    rule.matchers = body.matchers;
+    rule.enabled = body.enabled.unwrap_or(true);
+    rule.name = Some(body.name.unwrap_or_default());
+    rule.priority = Some(body.priority.unwrap_or(0));
+
+    success(&*rule)
+}
+
+async fn delete_rule(
+    State(state): State<SharedState>,
+    Path((zone_id, rule_id)): Path<(String, String)>,
+) -> HandlerResult {
+    let mut state = state.lock().unwrap();
+    let zone = state.zone_mut(&zone_id)?;
+
+    let Some(index) = zone.rules.iter().position(|rule| rule.id == rule_id) else {
+        return Err(not_found("rule"));
+    };
+
+    success(zone.rules.remove(index))
+}
+
+async fn get_catch_all(
+    State(state): State<SharedState>,
+    Path(zone_id): Path<String>,
+) -> HandlerResult {
+    let mut state = state.lock().unwrap();
+    success(&state.zone_mut(&zone_id)?.catch_all)
+}
+
+async fn update_catch_all(
+    State(state): State<SharedState>,
+    Path(zone_id): Path<String>,
+    Json(body): Json<RuleBody>,
+) -> HandlerResult {
+    let mut state = state.lock().unwrap();
+    let zone = state.zone_mut(&zone_id)?;
+
+    let only_matches_all = body
+        .matchers
+        .iter()
+        .all(|matcher| matches!(matcher.matcher_type, EmailRoutingRuleMatcherType::All));
+    if body.actions.is_empty() || body.matchers.is_empty() || !only_matches_all {
+        return Err(error(
+            StatusCode::BAD_REQUEST,
+            2007,
+            "Invalid catch-all rule",
+        ));
+    }
+
+    zone.catch_all.actions = body.actions;
+    zone.catch_all.enabled = body.enabled.unwrap_or(true);
+    zone.catch_all.name = Some(body.name.unwrap_or_default());
+
+    success(&zone.catch_all)
+}
+
+fn check_account(account_id: &str) -> Result<(), ApiError> {
+    if account_id == ACCOUNT_ID {
+        Ok(())
+    } else {
+        Err(not_found("account"))
+    }
+}
+
+async fn list_addresses(
+    State(state): State<SharedState>,
+    Path(account_id): Path<String>,
+    Query(query): Query<PageQuery>,
+) -> HandlerResult {
+    check_account(&account_id)?;
+    let state = state.lock().unwrap();
+    paginated(&state.addresses, &query, state.page_info)
+}
+
+async fn create_address(
+    State(state): State<SharedState>,
+    Path(account_id): Path<String>,
+    Json(body): Json<AddressBody>,
+) -> HandlerResult {
+    check_account(&account_id)?;
+    let mut state = state.lock().unwrap();
+
+    let exists = state.addresses.iter().any(|address| {
+        address
+            .email
+            .as_ref()
+            .is_some_and(|email| email.eq_ignore_ascii_case(&body.email))
+    });
+    if exists {
+        return Err(error(
+            StatusCode::CONFLICT,
+            2032,
+            "Destination address already exists",
+        ));
+    }
+
+    let address = Address {
+        id: Some(state.generate_id()),
+        created: Some(TIMESTAMP.to_string()),
+        email: Some(body.email),
+        modified: Some(TIMESTAMP.to_string()),
+        tag: None,
+        verified: None,
+    };
+
+    let response = success(&address);
+    state.addresses.push(address);
+    response
+}
+
+async fn get_address(
+    State(state): State<SharedState>,
+    Path((account_id, address_id)): Path<(String, String)>,
+) -> HandlerResult {
+    check_account(&account_id)?;
+    let state = state.lock().unwrap();
+
+    match state
+        .addresses
+        .iter()
+        .find(|address| address.id.as_deref() == Some(address_id.as_str()))
+    {
+        Some(address) => success(address),
+        None => Err(not_found("address")),
+    }
+}
+
+async fn delete_address(
+    State(state): State<SharedState>,
+    Path((account_id, address_id)): Path<(String, String)>,
+) -> HandlerResult {
+    check_account(&account_id)?;
+    let mut state = state.lock().unwrap();
+
+    let Some(index) = state
+        .addresses
+        .iter()
+        .position(|address| address.id.as_deref() == Some(address_id.as_str()))
+    else {
+        return Err(not_found("address"));
+    };
+
+    success(state.addresses.remove(index))
+}
@@ -0,0 +1,336 @@
+use crate::backup;
+use crate::cloudflare_api;
+use crate::command;
+use crate::config;
+use crate::manifest;
+use crate::state;
+use anyhow::Context;
+use chrono::SecondsFormat;
+use serde::Serialize;
+use std::io::Write;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default)]
+pub enum OutputFormat {
+    /// Aligned columns for humans
+    #[default]
+    Table,
+    Json,
+    Yaml,
+    Csv,
+}
+
+/// A value that can be shown as a row of the `table` and `csv` output formats.
+pub trait TableRow {
+    const HEADERS: &'static [&'static str];
+
+    fn row(&self) -> Vec<String>;
+}
+
+pub fn print_list<T: Serialize + TableRow>(
+    format: OutputFormat,
+    items: &[T],
+) -> anyhow::Result<()> {
+    let mut stdout = std::io::stdout().lock();
+
+    match format {
+        OutputFormat::Table => write_table(&mut stdout, items)?,
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut stdout, items).context("Failed to write json")?;
+            writeln!(stdout)?;
+        }
+        OutputFormat::Yaml => {
+            serde_yaml_ng::to_writer(&mut stdout, items).context("Failed to write yaml")?
+        }
+        OutputFormat::Csv => write_csv(&mut stdout, items)?,
+    }
+
+    Ok(())
+}
+
+pub fn print_item<T: Serialize + TableRow>(format: OutputFormat, item: &T) -> anyhow::Result<()> {
+    match format {
+        OutputFormat::Json | OutputFormat::Yaml => print_serialized(format, item),
+        OutputFormat::Table | OutputFormat::Csv => print_list(format, std::slice::from_ref(item)),
+    }
+}
+
+/// Prints an item with too many fields for a row. The `table` and `csv` formats show the given
+/// fields one per line, a field can be repeated for lists.
+pub fn print_details<T: Serialize>(
+    format: OutputFormat,
+    item: &T,
+    fields: &[(&str, String)],
+) -> anyhow::Result<()> {
+    match format {
+        OutputFormat::Json | OutputFormat::Yaml => print_serialized(format, item)?,
+        OutputFormat::Table => {
+            let mut stdout = std::io::stdout().lock();
+            let width = fields
+                .iter()
+                .map(|(field, _)| field.chars().count())
+                .max()
+                .unwrap_or(0);
+            for (field, value) in fields {
+                writeln!(stdout, "{}", format!("{field:width$}  {value}").trim_end())?;
+            }
+        }
+        OutputFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(std::io::stdout().lock());
+            writer.write_record(["FIELD", "VALUE"])?;
+            for (field, value) in fields {
+                writer.write_record([field, value.as_str()])?;
+            }
+            writer.flush()?;
+        }
+    }
+
+    Ok(())
+}
+
+fn print_serialized<T: Serialize>(format: OutputFormat, item: &T) -> anyhow::Result<()> {
+    let mut stdout = std::io::stdout().lock();
+
+    match format {
+        OutputFormat::Yaml => {
+            serde_yaml_ng::to_writer(&mut stdout, item).context("Failed to write yaml")?
+        }
+        _ => {
+            serde_json::to_writer_pretty(&mut stdout, item).context("Failed to write json")?;
+            writeln!(stdout)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn write_table<T: TableRow>(writer: &mut impl Write, items: &[T]) -> anyhow::Result<()> {
+    if items.is_empty() {
+        return Ok(());
+    }
+
+    let rows = items.iter().map(TableRow::row).collect::<Vec<_>>();
+
+    let mut widths = T::HEADERS
+        .iter()
+        .map(|header| header.chars().count())
+        .collect::<Vec<_>>();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let headers = T::HEADERS.iter().map(|header| header.to_string()).collect();
+    for row in std::iter::once(headers).chain(rows) {
+        let line = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        writeln!(writer, "{}", line.trim_end())?;
+    }
+
+    Ok(())
+}
+
+fn write_csv<T: TableRow>(writer: &mut impl Write, items: &[T]) -> anyhow::Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record(T::HEADERS)?;
+    for item in items {
+        writer.write_record(item.row())?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn join_display<T: std::fmt::Display>(items: &[T]) -> String {
+    items
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl TableRow for cloudflare_api::EmailRoutingRule {
+    const HEADERS: &'static [&'static str] =
+        &["ID", "MATCHERS", "ACTIONS", "NAME", "PRIORITY", "ENABLED"];
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.id.clone(),
+            join_display(&self.matchers),
+            join_display(&self.actions),
+            self.name.clone().unwrap_or_default(),
+            self.priority.map(|p| p.to_string()).unwrap_or_default(),
+            self.enabled.to_string(),
+        ]
+    }
+}
+
+impl TableRow for cloudflare_api::Address {
+    const HEADERS: &'static [&'static str] = &["ID", "EMAIL", "VERIFIED", "CREATED"];
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.id.clone().unwrap_or_default(),
+            self.email.clone().unwrap_or_default(),
+            self.verified
+                .clone()
+                .unwrap_or_else(|| "not verified".to_string()),
+            self.created.clone().unwrap_or_default(),
+        ]
+    }
+}
+
+impl TableRow for cloudflare_api::Zone {
+    const HEADERS: &'static [&'static str] = &["ID", "NAME", "ACCOUNT"];
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.id.clone(),
+            self.name.clone(),
+            self.account.name.clone(),
+        ]
+    }
+}
+
+/// The name of a unit variant as it's serialized, so the tables use the same one as json.
+fn variant_name<T: Serialize>(value: &T) -> String {
+    serde_json::to_value(value)
+        .ok()
+        .and_then(|value| value.as_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+fn describe_rule_request(rule: &cloudflare_api::CreateRoutingRuleRequest) -> String {
+    let mut description = join_display(&rule.actions);
+    if let Some(name) = &rule.name {
+        description.push_str(&format!(", Name: {name}"));
+    }
+    if let Some(priority) = rule.priority {
+        description.push_str(&format!(", Priority: {priority}"));
+    }
+    if rule.enabled == Some(false) {
+        description.push_str(", Disabled");
+    }
+    description
+}
+
+impl TableRow for manifest::PlannedOperation<'_> {
+    const HEADERS: &'static [&'static str] = &["ZONE", "OPERATION", "TARGET", "DETAILS"];
+
+    fn row(&self) -> Vec<String> {
+        let (operation, target, details) = match self.operation {
+            manifest::Operation::CreateDestination { email } => {
+                ("create-destination", email.clone(), String::new())
+            }
+            manifest::Operation::CreateRule { rule } => (
+                "create-rule",
+                join_display(&rule.matchers),
+                describe_rule_request(rule),
+            ),
+            manifest::Operation::UpdateRule { rule, changes, .. } => (
+                "update-rule",
+                join_display(&rule.matchers),
+                changes.join("; "),
+            ),
+            manifest::Operation::DeleteRule { rule } => (
+                "delete-rule",
+                join_display(&rule.matchers),
+                join_display(&rule.actions),
+            ),
+        };
+
+        vec![
+            self.zone.to_string(),
+            operation.to_string(),
+            target,
+            details,
+        ]
+    }
+}
+
+impl TableRow for config::ProfileSummary {
+    const HEADERS: &'static [&'static str] = &["NAME", "DEFAULT", "CREDENTIALS", "DEFAULT ZONE"];
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.name.clone(),
+            if self.default { "*" } else { "" }.to_string(),
+            self.credentials.to_string(),
+            self.default_zone.clone().unwrap_or_default(),
+        ]
+    }
+}
+
+impl TableRow for state::SweepResult {
+    const HEADERS: &'static [&'static str] = &["EMAIL", "EXPIRED", "STATUS", "DETAILS"];
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.email.clone(),
+            self.expired_at
+                .map(|expired_at| expired_at.to_rfc3339_opts(SecondsFormat::Secs, true))
+                .unwrap_or_default(),
+            variant_name(&self.status),
+            self.details.clone(),
+        ]
+    }
+}
+
+impl TableRow for command::DeleteResult {
+    const HEADERS: &'static [&'static str] = &["ID", "EMAIL", "STATUS", "DETAILS"];
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.id.clone(),
+            self.email.clone(),
+            variant_name(&self.status),
+            self.details.clone(),
+        ]
+    }
+}
+
+impl TableRow for backup::ImportResult {
+    const HEADERS: &'static [&'static str] = &["TARGET", "STATUS", "DETAILS"];
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.target.clone(),
+            variant_name(&self.status),
+            self.details.clone(),
+        ]
+    }
+}
+
+impl TableRow for cloudflare_api::EmailRoutingSettings {
+    const HEADERS: &'static [&'static str] = &["NAME", "ENABLED", "STATUS", "CREATED", "MODIFIED"];
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.name.clone(),
+            self.enabled.to_string(),
+            self.status
+                .as_ref()
+                .map(ToString::to_string)
+                .unwrap_or_default(),
+            self.created.clone().unwrap_or_default(),
+            self.modified.clone().unwrap_or_default(),
+        ]
+    }
+}
+
+impl TableRow for cloudflare_api::DnsRecord {
+    const HEADERS: &'static [&'static str] = &["TYPE", "NAME", "CONTENT", "PRIORITY", "TTL"];
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.record_type.clone(),
+            self.name.clone(),
+            self.content.clone(),
+            self.priority.map(|p| p.to_string()).unwrap_or_default(),
+            self.ttl.map(|ttl| ttl.to_string()).unwrap_or_default(),
+        ]
+    }
+}
@@ -0,0 +1,220 @@
+use crate::cloudflare_api::Credentials;
+use crate::config;
+use anyhow::{Context, bail};
+use argon2::Argon2;
+use base64::Engine;
+use base64::prelude::BASE64_STANDARD;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Passphrase to use instead of prompting for it, for scripts.
+pub const PASSPHRASE_ENV: &str = "CF_CONFIG_PASSPHRASE";
+/// How long the unlocked credentials are remembered without asking for the passphrase again.
+const SESSION_DURATION: Duration = Duration::from_secs(15 * 60);
+
+/// Credentials encrypted with XChaCha20-Poly1305, using a key derived from a passphrase with
+/// Argon2id. All the fields are base64 encoded.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EncryptedCredentials {
+    pub salt: String,
+    pub nonce: String,
+    pub ciphertext: String,
+}
+
+/// The key of the credentials unlocked in this session, kept in the runtime directory so it's
+/// gone after logging out.
+#[derive(Serialize, Deserialize)]
+struct Session {
+    /// Salt of the credentials the key unlocks.
+    salt: String,
+    key: String,
+    /// Unix timestamp, in seconds.
+    expires_at: u64,
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> anyhow::Result<[u8; 32]> {
+    let mut key = [0; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|err| anyhow::anyhow!("Failed to derive key from passphrase: {err}"))?;
+    Ok(key)
+}
+
+fn decode(field: &str, value: &str) -> anyhow::Result<Vec<u8>> {
+    BASE64_STANDARD
+        .decode(value)
+        .with_context(|| format!("Invalid {field} in the encrypted credentials"))
+}
+
+pub fn encrypt(
+    credentials: &Credentials,
+    passphrase: &str,
+) -> anyhow::Result<EncryptedCredentials> {
+    let salt = rand::random::<[u8; 16]>();
+    let nonce = rand::random::<[u8; NONCE_LENGTH]>();
+    let key = derive_key(passphrase, &salt)?;
+
+    let plaintext = serde_json::to_vec(credentials).context("Failed to serialize credentials")?;
+    let ciphertext = XChaCha20Poly1305::new(Key::from_slice(&key))
+        .encrypt(XNonce::from_slice(&nonce), plaintext.as_slice())
+        .map_err(|_| anyhow::anyhow!("Failed to encrypt credentials"))?;
+
+    let encrypted = EncryptedCredentials {
+        salt: BASE64_STANDARD.encode(salt),
+        nonce: BASE64_STANDARD.encode(nonce),
+        ciphertext: BASE64_STANDARD.encode(ciphertext),
+    };
+
+    // the user just typed the passphrase, don't ask for it again right away
+    save_session(&encrypted.salt, &key);
+
+    Ok(encrypted)
+}
+
+/// Length of the nonce of XChaCha20-Poly1305, in bytes.
+const NONCE_LENGTH: usize = 24;
+
+/// Fails when the credentials can't be decrypted whatever the passphrase, e.g. after editing
+/// them by hand.
+fn check_encrypted(encrypted: &EncryptedCredentials) -> anyhow::Result<()> {
+    decode("salt", &encrypted.salt)?;
+    decode("ciphertext", &encrypted.ciphertext)?;
+    let nonce = decode("nonce", &encrypted.nonce)?;
+    if nonce.len() != NONCE_LENGTH {
+        bail!(
+            "The encrypted credentials are corrupted: the nonce has {} bytes instead of {NONCE_LENGTH}",
+            nonce.len()
+        );
+    }
+    Ok(())
+}
+
+fn decrypt(encrypted: &EncryptedCredentials, key: &[u8]) -> Option<Credentials> {
+    let nonce = decode("nonce", &encrypted.nonce).ok()?;
+    let ciphertext = decode("ciphertext", &encrypted.ciphertext).ok()?;
+    if nonce.len() != NONCE_LENGTH {
+        return None;
+    }
+
+    let plaintext = XChaCha20Poly1305::new_from_slice(key)
+        .ok()?
+        .decrypt(XNonce::from_slice(&nonce), ciphertext.as_slice())
+        .ok()?;
+
+    serde_json::from_slice(&plaintext).ok()
+}
+
+/// Decrypts the credentials with the key of this session, or asks for the passphrase.
+pub fn unlock(encrypted: &EncryptedCredentials) -> anyhow::Result<Credentials> {
+    check_encrypted(encrypted)?;
+
+    if let Some(key) = load_session(&encrypted.salt)
+        && let Some(credentials) = decrypt(encrypted, &key)
+    {
+        return Ok(credentials);
+    }
+
+    let passphrase = read_passphrase("Passphrase to unlock the credentials: ")?;
+    let salt = decode("salt", &encrypted.salt)?;
+    let key = derive_key(&passphrase, &salt)?;
+
+    let Some(credentials) = decrypt(encrypted, &key) else {
+        bail!("Failed to decrypt the credentials. Is the passphrase correct?")
+    };
+
+    save_session(&encrypted.salt, &key);
+
+    Ok(credentials)
+}
+
+/// Forgets the credentials unlocked in this session. Returns whether they were unlocked.
+pub fn lock() -> anyhow::Result<bool> {
+    let Some(path) = session_path() else {
+        return Ok(false);
+    };
+
+    match std::fs::remove_file(&path) {
+        Ok(()) => Ok(true),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(err).with_context(|| format!("Failed to remove session at {path:?}")),
+    }
+}
+
+fn read_passphrase(prompt: &str) -> anyhow::Result<String> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV) {
+        return Ok(passphrase);
+    }
+
+    rpassword::prompt_password(prompt).with_context(|| {
+        format!("Failed to read the passphrase, it can be set with {PASSPHRASE_ENV}")
+    })
+}
+
+/// Asks for a new passphrase twice, to catch typos.
+pub fn read_new_passphrase() -> anyhow::Result<String> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV) {
+        if passphrase.is_empty() {
+            bail!("The passphrase in {PASSPHRASE_ENV} can't be empty");
+        }
+        return Ok(passphrase);
+    }
+
+    let passphrase = read_passphrase("New passphrase to encrypt the credentials: ")?;
+    if passphrase.is_empty() {
+        bail!("The passphrase can't be empty");
+    }
+    if read_passphrase("Repeat the passphrase: ")? != passphrase {
+        bail!("The passphrases don't match");
+    }
+
+    Ok(passphrase)
+}
+
+/// Only available on platforms with a runtime directory, elsewhere the passphrase is always asked.
+fn session_path() -> Option<PathBuf> {
+    ProjectDirs::from("", "", "cloudflare-api-client")?
+        .runtime_dir()
+        .map(|dir| dir.join("session.json"))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn load_session(salt: &str) -> Option<Vec<u8>> {
+    let content = std::fs::read(session_path()?).ok()?;
+    let session: Session = serde_json::from_slice(&content).ok()?;
+
+    if session.salt != salt || session.expires_at <= now() {
+        return None;
+    }
+
+    BASE64_STANDARD.decode(session.key).ok()
+}
+
+/// Failing to save the session isn't fatal, the passphrase is just asked again next time.
+fn save_session(salt: &str, key: &[u8]) {
+    let Some(path) = session_path() else {
+        return;
+    };
+
+    let session = Session {
+        salt: salt.to_string(),
+        key: BASE64_STANDARD.encode(key),
+        expires_at: now() + SESSION_DURATION.as_secs(),
+    };
+
+    let result = serde_json::to_vec(&session)
+        .map_err(anyhow::Error::from)
+        .and_then(|content| config::write_private_file(&path, &content));
+    if let Err(err) = result {
+        eprintln!("Warning: failed to remember the unlocked credentials: {err:#}");
+    }
+}
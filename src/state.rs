@@ -0,0 +1,154 @@
+use crate::cloudflare_api::EmailRoutingRule;
+use crate::config;
+use crate::output;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// What the tool remembers about the rules it created, which Cloudflare has no place for.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct State {
+    /// By rule id.
+    #[serde(default)]
+    pub rules: BTreeMap<String, RuleState>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RuleState {
+    pub zone_id: String,
+    /// The matcher of the rule, to recognize it in messages.
+    pub email: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    /// The service the alias was given to, with `create --for`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub site: Option<String>,
+    /// Rules created before the metadata was saved don't have it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+impl RuleState {
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags
+            .iter()
+            .any(|existing| existing.eq_ignore_ascii_case(tag))
+    }
+}
+
+/// How the state of a zone changed to match its rules.
+#[derive(Debug, Default)]
+pub struct Reconciliation {
+    /// Rules deleted outside the tool, whose state was dropped.
+    pub forgotten: Vec<String>,
+    /// Rules whose matchers were changed outside the tool.
+    pub renamed: Vec<String>,
+}
+
+impl State {
+    /// Updates the state of the rules of a zone to match the rules it actually has, as they
+    /// may have been changed in the dashboard or by other tools.
+    pub fn reconcile(&mut self, zone_id: &str, rules: &[EmailRoutingRule]) -> Reconciliation {
+        let mut reconciliation = Reconciliation::default();
+
+        self.rules.retain(|id, rule_state| {
+            if rule_state.zone_id != zone_id {
+                return true;
+            }
+
+            let Some(rule) = rules.iter().find(|rule| &rule.id == id) else {
+                reconciliation.forgotten.push(rule_state.email.clone());
+                return false;
+            };
+
+            let email = output::join_display(&rule.matchers);
+            if rule_state.email != email {
+                reconciliation.renamed.push(email.clone());
+                rule_state.email = email;
+            }
+            true
+        });
+
+        reconciliation
+    }
+
+    /// The rules that expired by `now`, oldest first.
+    pub fn expired(&self, now: DateTime<Utc>) -> Vec<(String, RuleState)> {
+        let mut expired = self
+            .rules
+            .iter()
+            .filter(|(_, rule)| rule.expires_at.is_some_and(|expires_at| expires_at <= now))
+            .map(|(id, rule)| (id.clone(), rule.clone()))
+            .collect::<Vec<_>>();
+        expired.sort_by_key(|(_, rule)| rule.expires_at);
+        expired
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum SweepStatus {
+    /// Found with `--dry-run`, nothing was changed.
+    Expired,
+    Deleted,
+    Disabled,
+    /// The rule no longer exists, it was forgotten.
+    Missing,
+    Skipped,
+    Failed,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SweepResult {
+    pub email: String,
+    pub expired_at: Option<DateTime<Utc>>,
+    pub status: SweepStatus,
+    pub details: String,
+}
+
+/// Kept with the user's data rather than the config, as it changes on every `create` and `sweep`.
+pub fn get_state_path() -> anyhow::Result<PathBuf> {
+    Ok(ProjectDirs::from("", "", "cloudflare-api-client")
+        .context("Failed to get project directories")?
+        .data_dir()
+        .join("state.json"))
+}
+
+pub fn load_state() -> anyhow::Result<State> {
+    let state_path = get_state_path()?;
+
+    if !state_path.is_file() {
+        return Ok(State::default());
+    }
+
+    let content = std::fs::read_to_string(&state_path)
+        .with_context(|| format!("Failed to read state at {state_path:?}"))?;
+
+    serde_json::from_str(&content)
+        .with_context(|| format!("Failed to deserialize state at {state_path:?}"))
+}
+
+pub fn save_state(state: &State) -> anyhow::Result<()> {
+    let state_path = get_state_path()?;
+
+    let content = serde_json::to_vec_pretty(state).context("Failed to serialize state")?;
+
+    // the aliases are as private as the credentials
+    config::write_private_file(&state_path, &content)
+        .with_context(|| format!("Failed to write state at {state_path:?}"))
+}
+
+pub fn forget_rule(rule_id: &str) -> anyhow::Result<()> {
+    let mut state = load_state()?;
+    if state.rules.remove(rule_id).is_some() {
+        save_state(&state)?;
+    }
+    Ok(())
+}
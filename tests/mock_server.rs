@@ -0,0 +1,1050 @@
+//! Runs the commands against the bundled mock server. Requires `--features mock-server`.
+#![cfg(feature = "mock-server")]
+
+use serde_json::Value;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use std::process::{Child, Command, Output, Stdio};
+use tempfile::TempDir;
+
+const BINARY: &str = env!("CARGO_BIN_EXE_cloudflare-mail-manager");
+
+struct MockServer {
+    process: Child,
+    url: String,
+}
+
+impl MockServer {
+    fn start(args: &[&str]) -> Self {
+        let mut process = Command::new(BINARY)
+            .arg("mock-server")
+            .args(args)
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("failed to start the mock server");
+
+        let mut line = String::new();
+        BufReader::new(process.stdout.take().unwrap())
+            .read_line(&mut line)
+            .expect("failed to read the mock server address");
+        let url = line
+            .trim()
+            .strip_prefix("Listening on ")
+            .expect("unexpected mock server output")
+            .to_string();
+
+        MockServer { process, url }
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+/// Runs the tool with its config isolated in a temporary directory.
+struct Cli {
+    server: MockServer,
+    home: TempDir,
+}
+
+impl Cli {
+    fn new() -> Self {
+        Cli::with_server_args(&[])
+    }
+
+    fn with_server_args(args: &[&str]) -> Self {
+        Cli {
+            server: MockServer::start(args),
+            home: TempDir::new().unwrap(),
+        }
+    }
+
+    fn run(&self, args: &[&str]) -> Output {
+        self.run_with_env(args, &[])
+    }
+
+    fn run_with_env(&self, args: &[&str], env: &[(&str, &str)]) -> Output {
+        Command::new(BINARY)
+            .args(args)
+            .env("CF_API_BASE_URL", &self.server.url)
+            .env("HOME", self.home.path())
+            .env("XDG_CONFIG_HOME", self.home.path().join("config"))
+            .env("XDG_DATA_HOME", self.home.path().join("data"))
+            .env("XDG_RUNTIME_DIR", self.home.path().join("runtime"))
+            .env_remove("CF_CONFIG_PASSPHRASE")
+            .env_remove("CF_API_TOKEN")
+            .env_remove("CF_EMAIL")
+            .env_remove("CF_API_KEY")
+            .envs(env.iter().copied())
+            .env("RUST_BACKTRACE", "0")
+            .output()
+            .unwrap()
+    }
+
+    fn success(&self, args: &[&str]) -> String {
+        self.success_with_env(args, &[])
+    }
+
+    fn success_with_env(&self, args: &[&str], env: &[(&str, &str)]) -> String {
+        let output = self.run_with_env(args, env);
+        assert!(
+            output.status.success(),
+            "{args:?} failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    fn config_path(&self) -> PathBuf {
+        self.home
+            .path()
+            .join("config/cloudflare-api-client/config.toml")
+    }
+
+    fn state_path(&self) -> PathBuf {
+        self.home
+            .path()
+            .join("data/cloudflare-api-client/state.json")
+    }
+
+    fn state(&self) -> Value {
+        serde_json::from_str(&std::fs::read_to_string(self.state_path()).unwrap()).unwrap()
+    }
+
+    fn json(&self, args: &[&str]) -> Value {
+        let mut args = args.to_vec();
+        args.extend(["--output", "json"]);
+        serde_json::from_str(&self.success(&args)).unwrap()
+    }
+
+    fn setup() -> Self {
+        let cli = Cli::new();
+        cli.success(&["setup", "--api-token", "token"]);
+        cli
+    }
+}
+
+#[test]
+fn commands_fail_without_setup() {
+    let cli = Cli::new();
+    assert!(!cli.run(&["list"]).status.success());
+}
+
+#[test]
+fn sets_up_with_global_api_key() {
+    let cli = Cli::new();
+    let output = cli.run(&["setup", "--email", "me@example.org", "--api-key", "key"]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("valid for me@example.org"), "{stderr}");
+
+    cli.success(&["list"]);
+}
+
+#[test]
+fn encrypts_credentials() {
+    let cli = Cli::new();
+    let passphrase = [("CF_CONFIG_PASSPHRASE", "correct horse")];
+
+    let setup = ["setup", "--api-token", "token", "--encrypt"];
+    let empty = cli.run_with_env(&setup, &[("CF_CONFIG_PASSPHRASE", "")]);
+    assert!(!empty.status.success());
+    assert!(!cli.config_path().exists());
+
+    cli.success_with_env(&["setup", "--api-token", "token", "--encrypt"], &passphrase);
+    let config = std::fs::read_to_string(cli.config_path()).unwrap();
+    assert!(config.contains("encrypted_credentials"), "{config}");
+    assert!(!config.contains("api_token"), "{config}");
+
+    // unlocked for the session by the setup
+    cli.success(&["list"]);
+
+    cli.success(&["lock"]);
+    assert!(!cli.run(&["list"]).status.success());
+    let wrong_passphrase = [("CF_CONFIG_PASSPHRASE", "wrong")];
+    assert!(
+        !cli.run_with_env(&["list"], &wrong_passphrase)
+            .status
+            .success()
+    );
+    cli.success_with_env(&["list"], &passphrase);
+}
+
+#[test]
+fn rejects_corrupted_credentials() {
+    let cli = Cli::new();
+    let passphrase = [("CF_CONFIG_PASSPHRASE", "correct horse")];
+    cli.success_with_env(&["setup", "--api-token", "token", "--encrypt"], &passphrase);
+    cli.success(&["lock"]);
+
+    let config = std::fs::read_to_string(cli.config_path()).unwrap();
+    let nonce = config
+        .lines()
+        .find(|line| line.starts_with("nonce"))
+        .unwrap();
+    std::fs::write(cli.config_path(), config.replace(nonce, "nonce = \"AAAA\"")).unwrap();
+
+    let output = cli.run_with_env(&["list"], &passphrase);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("corrupted"), "{stderr}");
+}
+
+#[test]
+fn warns_about_plaintext_credentials() {
+    let cli = Cli::new();
+    let output = cli.run(&["setup", "--api-token", "token"]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("PLAINTEXT"), "{stderr}");
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let metadata = std::fs::metadata(cli.config_path()).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+    }
+}
+
+#[test]
+fn uses_credentials_from_env() {
+    let cli = Cli::new();
+    let token = [("CF_API_TOKEN", "token")];
+    cli.success_with_env(&["list"], &token);
+
+    // the token isn't saved with the rest of the config
+    cli.success_with_env(&["zones", "--set-default", "example.com"], &token);
+    let config = std::fs::read_to_string(cli.config_path()).unwrap();
+    assert!(config.contains("default_zone"), "{config}");
+    assert!(!config.contains("token"), "{config}");
+    assert!(!cli.run(&["list"]).status.success());
+
+    let global_key = [("CF_EMAIL", "me@example.org"), ("CF_API_KEY", "key")];
+    cli.success_with_env(&["list"], &global_key);
+    assert!(
+        !cli.run_with_env(&["list"], &global_key[..1])
+            .status
+            .success()
+    );
+}
+
+#[test]
+fn uses_credential_command() {
+    let cli = Cli::new();
+    std::fs::create_dir_all(cli.config_path().parent().unwrap()).unwrap();
+    std::fs::write(cli.config_path(), "credential_command = \"echo token\"\n").unwrap();
+    cli.success(&["list"]);
+
+    std::fs::write(cli.config_path(), "credential_command = \"false\"\n").unwrap();
+    let output = cli.run(&["list"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("credential command `false` failed"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn manages_profiles() {
+    let cli = Cli::new();
+    cli.success(&["setup", "--profile", "personal", "--api-token", "token"]);
+    cli.success(&["setup", "--profile", "company", "--api-token", "other"]);
+
+    let profiles = cli.json(&["profile", "list"]);
+    assert_eq!(profiles[0]["name"], "company");
+    assert_eq!(profiles[0]["default"], false);
+    assert_eq!(profiles[1]["name"], "personal");
+    assert_eq!(profiles[1]["default"], true);
+
+    cli.success(&[
+        "--profile",
+        "company",
+        "create",
+        "shopping",
+        "me@example.org",
+    ]);
+    // an explicit profile wins over credentials in the environment
+    let env = [("CF_API_TOKEN", "from-env"), ("CF_EMAIL", "ci@example.org")];
+    let output = cli.run_with_env(&["--profile", "company", "list"], &env);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("ignoring CF_API_TOKEN, CF_EMAIL"),
+        "{stderr}"
+    );
+    let output = cli.run_with_env(&["list"], &env);
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("ignoring"));
+
+    cli.success(&["profile", "use", "company"]);
+    assert_eq!(cli.json(&["profile"])[0]["default"], true);
+    cli.success(&["delete", "shopping"]);
+
+    assert!(!cli.run(&["--profile", "other", "list"]).status.success());
+    assert!(!cli.run(&["profile", "use", "other"]).status.success());
+
+    cli.success(&["profile", "remove", "personal"]);
+    assert_eq!(cli.json(&["profile"]).as_array().unwrap().len(), 1);
+}
+
+#[test]
+fn migrates_legacy_config() {
+    let cli = Cli::new();
+    std::fs::create_dir_all(cli.config_path().parent().unwrap()).unwrap();
+    let legacy = "email = \"me@example.org\"\napi_token = \"token\"\napi_key = \"key\"\n";
+    std::fs::write(cli.config_path(), legacy).unwrap();
+    cli.success(&["list"]);
+
+    cli.success(&["zones", "--set-default", "example.com"]);
+    let config = std::fs::read_to_string(cli.config_path()).unwrap();
+    assert!(config.contains("[profiles.default]"), "{config}");
+
+    let profiles = cli.json(&["profile", "list"]);
+    assert_eq!(profiles[0]["name"], "default");
+    assert_eq!(profiles[0]["default"], true);
+    assert_eq!(profiles[0]["credentials"], "api token");
+    cli.success(&["list"]);
+}
+
+#[test]
+fn lists_zones() {
+    let cli = Cli::setup();
+    let zones = cli.json(&["zones"]);
+    assert_eq!(zones[0]["name"], "example.com");
+
+    let yaml = cli.success(&["zones", "--output", "yaml"]);
+    assert!(yaml.contains("name: example.com"), "{yaml}");
+}
+
+#[test]
+fn selects_zones() {
+    let zones = ["--zone-name", "example.com", "--zone-name", "example.net"];
+    let cli = Cli::with_server_args(&zones);
+    cli.success(&["setup", "--api-token", "token"]);
+
+    let output = cli.run(&["list"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Multiple zones found"), "{stderr}");
+    assert!(stderr.contains("example.net"), "{stderr}");
+
+    let zones = cli.json(&["zones"]);
+    assert_eq!(zones.as_array().unwrap().len(), 2);
+    let net_id = zones[1]["id"].as_str().unwrap();
+    assert_eq!(zones[1]["name"], "example.net");
+
+    cli.success(&["--zone", "EXAMPLE.NET", "create", "by-name", "drop"]);
+    cli.success(&["--zone", net_id, "create", "by-id", "drop"]);
+    assert!(!cli.run(&["--zone", "example.org", "list"]).status.success());
+
+    cli.success(&["zones", "--set-default", "example.net"]);
+    let config = std::fs::read_to_string(cli.config_path()).unwrap();
+    assert!(
+        config.contains(&format!("default_zone = \"{net_id}\"")),
+        "{config}"
+    );
+    assert_eq!(cli.json(&["list"]).as_array().unwrap().len(), 2);
+    let output = cli.run(&["list"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Selected zone: example.net"));
+
+    // --zone still wins over the default zone
+    let rules = cli.json(&["--zone", "example.com", "list"]);
+    assert_eq!(rules, Value::Array(vec![]));
+}
+
+#[test]
+fn manages_rules() {
+    let cli = Cli::setup();
+    assert_eq!(cli.json(&["list"]), Value::Array(vec![]));
+
+    cli.success(&["create", "shopping", "me@example.org", "--name", "Shopping"]);
+    let rules = cli.json(&["list"]);
+    assert_eq!(rules.as_array().unwrap().len(), 1);
+    assert_eq!(rules[0]["matchers"][0]["value"], "shopping@example.com");
+    assert_eq!(rules[0]["actions"][0]["value"][0], "me@example.org");
+    assert_eq!(rules[0]["name"], "Shopping");
+    assert_eq!(rules[0]["enabled"], true);
+
+    cli.success(&["update", "shopping", "--name", "Stores", "--priority", "3"]);
+    cli.success(&["disable", "shopping"]);
+    let rules = cli.json(&["list"]);
+    assert_eq!(rules[0]["name"], "Stores");
+    assert_eq!(rules[0]["priority"], 3);
+    assert_eq!(rules[0]["enabled"], false);
+
+    // rules that can't be told apart are errors, so scripts notice
+    cli.success(&["create", "shopping-old", "me@example.org"]);
+    for args in [
+        &["show", "nope"][..],
+        &["update", "nope", "--name", "x"],
+        &["disable", "nope"],
+        &["delete", "shopping"],
+    ] {
+        let output = cli.run(args);
+        assert_eq!(output.status.code(), Some(1), "{args:?}");
+        assert!(output.stdout.is_empty(), "{args:?}");
+    }
+    let output = cli.run(&["delete", "shopping"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("More than one rule found"), "{stderr}");
+    assert!(stderr.contains("shopping-old@example.com"), "{stderr}");
+
+    cli.success(&["delete", "shopping@"]);
+    cli.success(&["delete", "shopping-old"]);
+    assert_eq!(cli.json(&["list"]), Value::Array(vec![]));
+}
+
+#[test]
+fn refuses_existing_matchers() {
+    let cli = Cli::setup();
+    cli.success(&["create", "shopping", "me@example.org", "--name", "Shopping"]);
+
+    let output = cli.run(&["create", "SHOPPING@example.com", "me@example.org"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("already exists"), "{stderr}");
+
+    cli.success(&["addresses", "add", "other@example.org"]);
+    cli.success(&["create", "shopping", "other@example.org", "--force"]);
+    let rules = cli.json(&["list"]);
+    assert_eq!(rules.as_array().unwrap().len(), 1);
+    assert_eq!(rules[0]["actions"][0]["value"][0], "other@example.org");
+    assert_eq!(rules[0]["name"], "Shopping");
+}
+
+#[test]
+fn generates_aliases() {
+    let cli = Cli::setup();
+    let alias = |rules: &Value, index: usize| {
+        let value = rules[index]["matchers"][0]["value"].as_str().unwrap();
+        value.strip_suffix("@example.com").unwrap().to_string()
+    };
+
+    cli.success(&[
+        "create", "--style", "words", "--length", "3", "--prefix", "shop.",
+    ]);
+    let words = alias(&cli.json(&["list"]), 0);
+    let words = words
+        .strip_prefix("shop.")
+        .unwrap()
+        .split('-')
+        .collect::<Vec<_>>();
+    assert_eq!(words.len(), 4, "{words:?}");
+    assert!(words[3].parse::<u32>().is_ok(), "{words:?}");
+
+    // defaults from the config, overridden by the options
+    let mut config = std::fs::read_to_string(cli.config_path()).unwrap();
+    config.push_str("\n[profiles.default.alias]\nstyle = \"random\"\nalphabet = \"ab\"\n");
+    std::fs::write(cli.config_path(), config).unwrap();
+    cli.success(&["create"]);
+    cli.success(&["create", "--length", "40"]);
+
+    let rules = cli.json(&["list"]);
+    let random = (0..3).map(|index| alias(&rules, index)).collect::<Vec<_>>();
+    let default_length = random
+        .iter()
+        .find(|alias| alias.len() == 16 && !alias.contains('-'))
+        .unwrap();
+    assert!(
+        default_length
+            .chars()
+            .all(|char| char == 'a' || char == 'b')
+    );
+    assert!(random.iter().any(|alias| alias.len() == 40), "{random:?}");
+
+    cli.success(&["zones", "--set-default", "example.com"]);
+    let config = std::fs::read_to_string(cli.config_path()).unwrap();
+    assert!(config.contains("alphabet = \"ab\""), "{config}");
+
+    // refused before sending anything, rather than with an unclear error from the api
+    for invalid in [
+        &["--alphabet", "a@b"][..],
+        &["--prefix", "bad name "],
+        &["--prefix", "."],
+        &["--suffix", "."],
+    ] {
+        let output = cli.run(&[&["create"][..], invalid].concat());
+        assert_eq!(output.status.code(), Some(1), "{invalid:?}");
+    }
+    assert_eq!(cli.json(&["list"]).as_array().unwrap().len(), 3);
+}
+
+#[test]
+fn generates_aliases_for_services() {
+    let cli = Cli::setup();
+    let matcher = |rule: &Value| rule["matchers"][0]["value"].as_str().unwrap().to_string();
+
+    cli.success(&["create", "--for", "https://www.GitHub.com/login"]);
+    let rules = cli.json(&["list"]);
+    assert_eq!(rules[0]["name"], "github.com");
+    let random = matcher(&rules[0]);
+    assert!(random.starts_with("github.com."), "{random}");
+    assert_eq!(random.len(), "github.com.1234@example.com".len());
+    cli.success(&["delete", &random]);
+
+    let mut config = std::fs::read_to_string(cli.config_path()).unwrap();
+    config.push_str("\n[profiles.default.alias]\nhmac_key = \"secret\"\n");
+    std::fs::write(cli.config_path(), config).unwrap();
+
+    // the same service and key always give the same alias
+    cli.success(&["create", "--for", "github.com"]);
+    let keyed = matcher(&cli.json(&["list"])[0]);
+    cli.success(&["delete", &keyed]);
+    cli.success(&["create", "--for", "github.com"]);
+    assert_eq!(matcher(&cli.json(&["list"])[0]), keyed);
+    assert!(!cli.run(&["create", "--for", "github.com"]).status.success());
+
+    cli.success(&[
+        "create",
+        "--for",
+        "gitlab.com",
+        "--template",
+        "{hmac4}.{service}",
+    ]);
+    let rules = cli.json(&["list"]);
+    let rule = rules
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|rule| rule["name"] == "gitlab.com")
+        .unwrap();
+    assert!(matcher(rule).ends_with(".gitlab.com@example.com"), "{rule}");
+
+    let output = cli.run(&["create", "--for", "x.com", "--template", "{unknown}"]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn sweeps_expired_rules() {
+    let cli = Cli::setup();
+    cli.success(&["create", "expired", "me@example.org", "--ttl", "0s"]);
+    cli.success(&["create", "later", "me@example.org", "--ttl", "7d"]);
+    cli.success(&["create", "forever", "me@example.org"]);
+
+    let swept = cli.json(&["sweep", "--dry-run"]);
+    assert_eq!(swept.as_array().unwrap().len(), 1);
+    assert_eq!(swept[0]["email"], "expired@example.com");
+    assert_eq!(swept[0]["status"], "expired");
+    assert_eq!(cli.json(&["list"]).as_array().unwrap().len(), 3);
+
+    assert_eq!(cli.json(&["sweep", "--disable"])[0]["status"], "disabled");
+    assert_eq!(cli.json(&["sweep", "--disable"])[0]["status"], "skipped");
+
+    assert_eq!(cli.json(&["sweep"])[0]["status"], "deleted");
+    let rules = cli.json(&["list"]);
+    assert_eq!(rules.as_array().unwrap().len(), 2);
+    assert!(
+        rules
+            .as_array()
+            .unwrap()
+            .iter()
+            .all(|rule| rule["enabled"] == true)
+    );
+    assert_eq!(cli.json(&["sweep"]), Value::Array(vec![]));
+}
+
+#[test]
+fn keeps_rule_metadata() {
+    let cli = Cli::setup();
+    cli.success(&["create", "shop", "--tag", "shopping", "--tag", "online"]);
+    cli.success(&["create", "--for", "github.com", "--note", "work account"]);
+    cli.success(&["create", "news"]);
+
+    let rules = cli.json(&["list", "--tag", "Shopping"]);
+    assert_eq!(rules.as_array().unwrap().len(), 1);
+    assert_eq!(rules[0]["matchers"][0]["value"], "shop@example.com");
+
+    let state = cli.state();
+    let metadata = state["rules"].as_object().unwrap();
+    assert_eq!(metadata.len(), 3);
+    let github = metadata
+        .values()
+        .find(|rule| rule["site"] == "github.com")
+        .unwrap();
+    assert_eq!(github["note"], "work account");
+    assert!(github["created_at"].is_string());
+
+    // rules changed outside the tool
+    cli.success(&["update", "shop", "--matcher", "store"]);
+    let mut state = cli.state();
+    let zone_id = cli.json(&["zones"])[0]["id"].clone();
+    state["rules"]["deleted-rule"] =
+        serde_json::json!({"zone_id": zone_id, "email": "gone@example.com"});
+    let path = cli
+        .home
+        .path()
+        .join("data/cloudflare-api-client/state.json");
+    std::fs::write(path, state.to_string()).unwrap();
+
+    let output = cli.run(&["list"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("deleted outside the tool: gone@example.com"),
+        "{stderr}"
+    );
+    assert!(
+        stderr.contains("changed outside the tool: store@example.com"),
+        "{stderr}"
+    );
+
+    let state = cli.state();
+    assert_eq!(state["rules"].as_object().unwrap().len(), 3);
+    assert_eq!(
+        cli.json(&["list", "--tag", "online"])
+            .as_array()
+            .unwrap()
+            .len(),
+        1
+    );
+}
+
+#[test]
+fn filters_rules() {
+    let cli = Cli::setup();
+    cli.success(&["addresses", "add", "old@work.example"]);
+    cli.success(&["create", "shop-a", "old@work.example", "--name", "Zebra"]);
+    cli.success(&["create", "shop-b", "me@example.org", "--name", "Apple"]);
+    cli.success(&["create", "news", "drop", "--disabled"]);
+
+    let matchers = |args: &[&str]| {
+        let mut args = [&["list"], args].concat();
+        args.extend(["--sort", "matcher"]);
+        cli.json(&args)
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|rule| rule["matchers"][0]["value"].as_str().unwrap().to_string())
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        matchers(&["shop"]),
+        ["shop-a@example.com", "shop-b@example.com"]
+    );
+    assert_eq!(
+        matchers(&["SHOP-?@*"]),
+        ["shop-a@example.com", "shop-b@example.com"]
+    );
+    assert_eq!(
+        matchers(&["shop"]).len(),
+        matchers(&["^shop-[ab]@", "--regex"]).len()
+    );
+    assert_eq!(matchers(&["shop-?"]), Vec::<String>::new());
+    assert_eq!(
+        matchers(&["--destination", "@work."]),
+        ["shop-a@example.com"]
+    );
+    assert_eq!(matchers(&["--action", "drop"]), ["news@example.com"]);
+    assert_eq!(matchers(&["--enabled", "false"]), ["news@example.com"]);
+    assert_eq!(matchers(&["--name", "apple"]), ["shop-b@example.com"]);
+
+    let names = cli.json(&["list", "--sort", "name", "--limit", "2"]);
+    assert_eq!(names.as_array().unwrap().len(), 2);
+    assert_eq!(names[0]["name"], "Apple");
+    assert_eq!(names[1]["name"], "Zebra");
+
+    assert!(!cli.run(&["list", "(", "--regex"]).status.success());
+}
+
+#[test]
+fn deletes_matching_rules() {
+    let cli = Cli::setup();
+    for alias in ["test-1", "test-2", "test-3"] {
+        cli.success(&["create", alias, "me@example.org", "--ttl", "1d"]);
+    }
+    cli.success(&["create", "keep", "me@example.org", "--ttl", "1d"]);
+    cli.success(&["create", "contest", "me@example.org"]);
+
+    // without wildcards the pattern is the whole matcher, not a part of it
+    let exact = cli.json(&["delete", "--all-matching", "test", "--yes"]);
+    assert_eq!(exact, Value::Array(vec![]));
+    let exact = cli.json(&["delete", "--all-matching", "contest@example.com", "--yes"]);
+    assert_eq!(exact.as_array().unwrap().len(), 1);
+    assert!(
+        !cli.run(&["delete", "--all-matching", "", "--yes"])
+            .status
+            .success()
+    );
+
+    // there is no terminal to ask for confirmation on
+    assert!(
+        !cli.run(&["delete", "--all-matching", "test-*"])
+            .status
+            .success()
+    );
+    assert_eq!(cli.json(&["list"]).as_array().unwrap().len(), 4);
+    assert!(!cli.run(&["delete"]).status.success());
+
+    let results = cli.json(&[
+        "delete",
+        "--all-matching",
+        "^test-[12]@",
+        "--regex",
+        "--yes",
+    ]);
+    let results = results.as_array().unwrap();
+    assert_eq!(results.len(), 2);
+    assert!(results.iter().all(|result| result["status"] == "deleted"));
+    assert_eq!(results[0]["email"], "test-1@example.com");
+
+    let table = cli.success(&["delete", "--all-matching", "TEST-*", "-y"]);
+    assert!(table.contains("test-3@example.com  deleted"), "{table}");
+
+    let rules = cli.json(&["list"]);
+    assert_eq!(rules.as_array().unwrap().len(), 1);
+    assert_eq!(rules[0]["matchers"][0]["value"], "keep@example.com");
+    assert_eq!(cli.state()["rules"].as_object().unwrap().len(), 1);
+
+    let none = cli.json(&["delete", "--all-matching", "test-*", "--yes"]);
+    assert_eq!(none, Value::Array(vec![]));
+}
+
+#[test]
+fn shows_rule_details() {
+    let cli = Cli::setup();
+    cli.success(&["addresses", "add", "other@example.org"]);
+    cli.success(&[
+        "create",
+        "shop",
+        "me@example.org",
+        "--tag",
+        "shopping",
+        "--note",
+        "Sales",
+    ]);
+    cli.success(&[
+        "update",
+        "shop",
+        "--action",
+        "me@example.org",
+        "--action",
+        "other@example.org",
+    ]);
+
+    let rule = cli.json(&["show", "shop"]);
+    assert_eq!(rule["tag"], rule["id"]);
+    assert_eq!(rule["matchers"][0]["field"], "to");
+    assert_eq!(rule["actions"].as_array().unwrap().len(), 2);
+    assert!(rule["destinations"][0]["verified"].is_string());
+    assert_eq!(rule["destinations"][1]["verified"], Value::Null);
+    assert_eq!(rule["metadata"]["tags"][0], "shopping");
+
+    let table = cli.success(&["show", "shop"]);
+    assert!(
+        table.contains("MATCHER      shop@example.com (field: to)"),
+        "{table}"
+    );
+    assert!(
+        table.contains("other@example.org (not verified)"),
+        "{table}"
+    );
+    assert!(table.contains("NOTE         Sales"), "{table}");
+
+    // the field is the one the api has, rules made elsewhere may match another one
+    let backup = cli.home.path().join("backup.json");
+    std::fs::write(
+        &backup,
+        r#"{
+            "version": 1,
+            "zone": "example.com",
+            "rules": [{
+                "id": "imported",
+                "enabled": true,
+                "matchers": [{ "type": "literal", "field": "cc", "value": "list@example.com" }],
+                "actions": [{ "type": "drop" }],
+                "name": null,
+                "priority": null
+            }]
+        }"#,
+    )
+    .unwrap();
+    cli.success(&["import", backup.to_str().unwrap()]);
+    let table = cli.success(&["show", "list@"]);
+    assert!(table.contains("list@example.com (field: cc)"), "{table}");
+}
+
+#[test]
+fn applies_manifests() {
+    let cli = Cli::setup();
+    cli.success(&["create", "old", "me@example.org"]);
+
+    let manifest = cli.home.path().join("manifest.toml");
+    let manifest_arg = manifest.to_str().unwrap();
+    std::fs::write(
+        &manifest,
+        r#"
+        [[zones]]
+        destinations = ["new@example.org"]
+
+        [[zones.rules]]
+        matcher = "shopping"
+        actions = ["me@example.org"]
+        name = "Shopping"
+
+        [[zones.rules]]
+        matcher = "news@example.com"
+        actions = ["drop"]
+        "#,
+    )
+    .unwrap();
+
+    let plan = cli.json(&["plan", manifest_arg]);
+    let operations = plan.as_array().unwrap();
+    assert_eq!(operations.len(), 3);
+    assert_eq!(operations[0]["operation"], "create-destination");
+    assert_eq!(operations[0]["email"], "new@example.org");
+    assert_eq!(operations[1]["operation"], "create-rule");
+    assert_eq!(
+        operations[1]["rule"]["matchers"][0]["value"],
+        "shopping@example.com"
+    );
+    assert_eq!(operations[1]["rule"]["name"], "Shopping");
+    assert_eq!(operations[2]["rule"]["actions"][0]["type"], "drop");
+    // planning doesn't change anything
+    assert_eq!(cli.json(&["list"]).as_array().unwrap().len(), 1);
+
+    cli.success(&["apply", manifest_arg]);
+    assert_eq!(cli.json(&["list"]).as_array().unwrap().len(), 3);
+    assert_eq!(cli.json(&["addresses"]).as_array().unwrap().len(), 2);
+    assert_eq!(cli.json(&["plan", manifest_arg]), Value::Array(vec![]));
+
+    std::fs::write(
+        &manifest,
+        r#"
+        [[zones]]
+
+        [[zones.rules]]
+        matcher = "shopping"
+        actions = ["me@example.org"]
+        priority = 5
+        enabled = false
+        "#,
+    )
+    .unwrap();
+
+    let plan = cli.json(&["plan", manifest_arg, "--prune"]);
+    let operations = plan.as_array().unwrap();
+    assert_eq!(operations.len(), 3);
+    assert_eq!(operations[0]["operation"], "update-rule");
+    assert_eq!(operations[0]["changes"][0], "priority: 0 -> 5");
+    assert_eq!(operations[0]["changes"][1], "enabled: true -> false");
+    // the name isn't in the manifest anymore, so it's kept
+    assert_eq!(operations[0]["rule"]["name"], "Shopping");
+    let deleted = operations[1..]
+        .iter()
+        .map(|operation| {
+            assert_eq!(operation["operation"], "delete-rule");
+            operation["rule"]["matchers"][0]["value"].as_str().unwrap()
+        })
+        .collect::<std::collections::BTreeSet<_>>();
+    assert_eq!(
+        deleted,
+        ["news@example.com", "old@example.com"]
+            .into_iter()
+            .collect()
+    );
+
+    cli.success(&["apply", manifest_arg, "--prune"]);
+    let rules = cli.json(&["list"]);
+    assert_eq!(rules.as_array().unwrap().len(), 1);
+    assert_eq!(rules[0]["matchers"][0]["value"], "shopping@example.com");
+    assert_eq!(rules[0]["priority"], 5);
+    assert_eq!(rules[0]["enabled"], false);
+    assert_eq!(
+        cli.json(&["plan", manifest_arg, "--prune"]),
+        Value::Array(vec![])
+    );
+}
+
+#[test]
+fn exports_and_imports_rules() {
+    let zones = ["--zone-name", "example.com", "--zone-name", "example.net"];
+    let cli = Cli::with_server_args(&zones);
+    cli.success(&["setup", "--api-token", "token"]);
+    cli.success(&["--zone", "example.com", "create", "shop", "me@example.org"]);
+    cli.success(&["--zone", "example.com", "create", "news", "drop"]);
+
+    let path = |name: &str| cli.home.path().join(name).to_str().unwrap().to_string();
+    let json = path("backup.json");
+    let toml = path("backup.toml");
+    let misnamed = path("toml.json");
+    cli.success(&["--zone", "example.com", "export", &json]);
+    cli.success(&["--zone", "example.com", "export", &toml]);
+    cli.success(&[
+        "--zone",
+        "example.com",
+        "export",
+        &misnamed,
+        "--format",
+        "toml",
+    ]);
+    assert!(
+        std::fs::read_to_string(&toml)
+            .unwrap()
+            .contains("version = 1")
+    );
+
+    let status = |results: &Value, target: &str| {
+        let results = results.as_array().unwrap();
+        results
+            .iter()
+            .find(|result| result["target"] == target)
+            .unwrap_or_else(|| panic!("{target} not in {results:?}"))["status"]
+            .clone()
+    };
+    let results = cli.json(&["--zone", "example.net", "import", &json]);
+    assert_eq!(status(&results, "me@example.org"), "skipped");
+    assert_eq!(status(&results, "shop@example.net"), "created");
+    assert_eq!(status(&results, "news@example.net"), "created");
+
+    let rules = cli.json(&["--zone", "example.net", "list", "--sort", "matcher"]);
+    assert_eq!(rules[0]["matchers"][0]["value"], "news@example.net");
+    assert_eq!(rules[0]["actions"][0]["type"], "drop");
+    assert_eq!(rules[1]["matchers"][0]["value"], "shop@example.net");
+
+    // the second import finds the rules of the first one
+    cli.success(&[
+        "--zone",
+        "example.net",
+        "update",
+        "news",
+        "--action",
+        "me@example.org",
+    ]);
+    let results = cli.json(&["--zone", "example.net", "import", &toml]);
+    assert_eq!(status(&results, "shop@example.net"), "skipped");
+    assert_eq!(status(&results, "news@example.net"), "conflict");
+    assert_eq!(
+        cli.json(&["--zone", "example.net", "list"])
+            .as_array()
+            .unwrap()
+            .len(),
+        2
+    );
+
+    // the extension is only a default
+    let import = &["--zone", "example.net", "import", misnamed.as_str()];
+    assert!(!cli.run(import).status.success());
+    cli.success(&[&import[..], &["--format", "toml"]].concat());
+}
+
+#[test]
+fn lists_every_page() {
+    // the pages may report the number of pages, only the number of items, or neither
+    for page_info in ["total-pages", "total-count", "count"] {
+        let cli = Cli::with_server_args(&["--page-info", page_info]);
+        cli.success(&["setup", "--api-token", "token"]);
+
+        // more rules than fit in the largest page of the api, with a last page that isn't full
+        let manifest = cli.home.path().join("manifest.toml");
+        let rules = (0..120)
+            .map(|i| format!("[[zones.rules]]\nmatcher = \"rule-{i}\"\nactions = [\"drop\"]\n"))
+            .collect::<String>();
+        std::fs::write(&manifest, format!("[[zones]]\n{rules}")).unwrap();
+        let manifest = manifest.to_str().unwrap();
+        cli.success(&["apply", manifest]);
+
+        let rules = cli.json(&["list"]);
+        let matchers = rules
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|rule| rule["matchers"][0]["value"].as_str().unwrap())
+            .collect::<std::collections::BTreeSet<_>>();
+        assert_eq!(matchers.len(), 120, "{page_info}");
+        assert!(matchers.contains("rule-0@example.com"), "{page_info}");
+        assert!(matchers.contains("rule-119@example.com"), "{page_info}");
+        assert_eq!(cli.json(&["plan", manifest]), Value::Array(vec![]));
+    }
+}
+
+#[test]
+fn manages_catch_all() {
+    let cli = Cli::setup();
+    cli.success(&["catch-all", "forward", "me@example.org"]);
+
+    let catch_all = cli.json(&["catch-all", "show"]);
+    assert_eq!(catch_all["enabled"], true);
+    assert_eq!(catch_all["actions"][0]["type"], "forward");
+
+    cli.success(&["catch-all", "disable"]);
+    assert_eq!(cli.json(&["catch-all", "show"])["enabled"], false);
+
+    // there is nowhere to keep them, so they aren't silently dropped
+    for metadata in [["--tag", "x"], ["--note", "n"], ["--ttl", "1d"]] {
+        let output = cli.run(&[&["create", "*", "drop"][..], &metadata].concat());
+        assert_eq!(output.status.code(), Some(1), "{metadata:?}");
+    }
+    assert_eq!(cli.json(&["catch-all", "show"])["enabled"], false);
+}
+
+#[test]
+fn manages_addresses() {
+    let cli = Cli::setup();
+    cli.success(&["addresses", "add", "other@example.org"]);
+
+    let addresses = cli.json(&["addresses"]);
+    assert_eq!(addresses.as_array().unwrap().len(), 2);
+
+    let address = cli.json(&["addresses", "show", "other@example.org"]);
+    assert_eq!(address["verified"], Value::Null);
+
+    assert_eq!(
+        cli.run(&["addresses", "show", "nope"]).status.code(),
+        Some(1)
+    );
+    let output = cli.run(&["addresses", "remove", "example.org"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("More than one address found"), "{stderr}");
+
+    cli.success(&["addresses", "remove", "other@example.org"]);
+    assert_eq!(cli.json(&["addresses"]).as_array().unwrap().len(), 1);
+}
+
+#[test]
+fn manages_routing() {
+    let cli = Cli::setup();
+    cli.success(&["routing", "disable"]);
+    assert_eq!(cli.json(&["routing", "status"])["enabled"], false);
+
+    cli.success(&["routing", "enable"]);
+    assert_eq!(cli.json(&["routing", "status"])["status"], "ready");
+}
+
+#[test]
+fn reports_api_errors() {
+    let cli = Cli::setup();
+    cli.success(&["create", "shopping", "me@example.org"]);
+    cli.success(&["create", "travel", "me@example.org"]);
+
+    let output = cli.run(&["update", "travel", "--matcher", "shopping"]);
+    assert_eq!(output.status.code(), Some(8));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Duplicated rule (code 2020)"), "{stderr}");
+}
+
+#[test]
+fn retries_rate_limited_requests() {
+    let cli = Cli::with_server_args(&["--rate-limited-requests", "2"]);
+
+    let output = cli.run(&["setup", "--api-token", "token"]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("429 Too Many Requests"), "{stderr}");
+    assert!(stderr.contains("attempt 3/4"), "{stderr}");
+}
+
+#[test]
+fn gives_up_on_long_rate_limits() {
+    let cli = Cli::with_server_args(&["--rate-limited-requests", "1", "--retry-after", "600"]);
+
+    let output = cli.run(&["setup", "--api-token", "token"]);
+    assert_eq!(output.status.code(), Some(6));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("(retry after 600s)"), "{stderr}");
+    assert!(!stderr.contains("Retrying"), "{stderr}");
+}